@@ -1,4 +1,4 @@
-/**
+/*!
 * Top level account names (TLAs) are very valuable as they provide root of trust and discoverability for 
 * companies, applications and users. To allow for fair access to them, the top level account names that 
 * are shorter than MIN_ALLOWED_TOP_LEVEL_ACCOUNT_LENGTH characters (32 at time of writing) will be auctioned off.
@@ -12,54 +12,681 @@
 *  - Proceeds of the auctions then get burned by the naming contract, benefiting all the token holders.
 *  - Done: account was claimed and created, the auction is done and all state will be cleared except that 
 *    this name is in done collection. On claim also withdraws all other bids automatically.
+*  - Besides the sealed auctions, names can be auctioned in the open or candle format, sold in a dutch auction
+*    once their week ended without bids, registered at a fixed price, reserved by the owner or pre-allocated.
+*    Bids can be placed for a beneficiary, signed off-chain, placed blind or revealed in a fungible token,
+*    and claimed names are minted as NEP-171 tokens. The methods describe the rules of each.
+*  - The state has no migration from the first version of the registrar, whose layout it doesn't read:
+*    upgrading a deployed registrar needs a new account, or its state cleared, and `new` called again.
 */
 
-use near_sdk::json_types::Base58PublicKey;
-use near_sdk::{env, near_bindgen, wee_alloc, AccountId, Balance, Promise, BlockHeight};
-use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, ext_contract, near_bindgen, wee_alloc, AccountId, Balance, Promise, PromiseResult, BlockHeight, Gas};
+use near_sdk::collections::{TreeMap, UnorderedMap, UnorderedSet};
 use borsh::{BorshDeserialize, BorshSerialize};
+use std::convert::TryFrom;
+use std::ops::Bound;
 use std::str;
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hasher}; 
 
+
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+/// Names at least this long, without the suffix, are not auctioned and can be registered directly.
+const MIN_ALLOWED_TOP_LEVEL_ACCOUNT_LENGTH: usize = 32;
+/// Gas attached to the callback checking the outcome of the account creation in `claim`.
+const ON_CLAIM_GAS: Gas = 20_000_000_000_000;
+/// Gas attached to the init call of the contract deployed on claim.
+const INIT_GAS: Gas = 20_000_000_000_000;
+/// Gas attached to the callback checking the outcome of a refund transfer.
+const ON_REFUND_GAS: Gas = 10_000_000_000_000;
+/// Gas attached to the `ft_transfer` of a fungible token refund.
+const FT_TRANSFER_GAS: Gas = 10_000_000_000_000;
+/// Gas attached to `nft_on_transfer` in `nft_transfer_call`.
+const NFT_ON_TRANSFER_GAS: Gas = 25_000_000_000_000;
+/// Gas attached to the callback resolving `nft_transfer_call`.
+const NFT_RESOLVE_TRANSFER_GAS: Gas = 10_000_000_000_000;
+/// Expired blind bids removed each time a blind bid is placed or an auction is settled by a claim.
+const EXPIRED_BLIND_BIDS_LIMIT: u64 = 10;
+/// Price of a byte of storage, paid by the deposit of a blind bid.
+const STORAGE_PRICE_PER_BYTE: Balance = 10_000_000_000_000_000_000;
+
+#[ext_contract(ext_self)]
+pub trait ExtRegistrar {
+    fn on_claim(&mut self, account_id: AccountId, winning_account_id: AccountId) -> bool;
+    fn on_refund(&mut self, account_id: AccountId, amount: U128) -> bool;
+    fn on_token_refund(&mut self, token_id: AccountId, account_id: AccountId, amount: U128) -> bool;
+    fn nft_resolve_transfer(&mut self, previous_owner_id: AccountId, receiver_id: AccountId, token_id: String) -> bool;
+}
+
+#[ext_contract(ext_nft_receiver)]
+pub trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(&mut self, sender_id: AccountId, previous_owner_id: AccountId, token_id: String, msg: String) -> bool;
+}
+
+#[ext_contract(ext_ft)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct Bid {
     amount: Balance,
-    commitment: Vec<u8>
+    commitment: Vec<u8>,
+    block_height: BlockHeight,
+    // account the bid is placed for, the bidder if not set
+    beneficiary_account_id: Option<AccountId>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct BlindBid {
+    bidder_account_id: AccountId,
+    block_height: BlockHeight,
+    // deposit covering the storage of the blind bid, refunded once it is disclosed or expired
+    deposit: Balance,
+}
+
+/// Outcome of the account creation started by `claim`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ClaimStatus {
+    /// The name wasn't claimed yet.
+    Unclaimed,
+    /// The account is being created.
+    Pending,
+    /// The account was created for the winner.
+    Claimed,
+    /// The account couldn't be created, the winner was refunded and the name can be auctioned again.
+    Failed,
+}
+
+/// Format of an auction.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AuctionKind {
+    /// Commit-reveal bids, the winner pays the second highest bid.
+    Sealed,
+    /// Public escrowed bids, the winner pays its own bid.
+    Open,
+    /// Descending price for names nobody bid on, the first buyer pays the current price.
+    Dutch,
+    /// Public escrowed bids closed at a random block of the bidding period, the winner pays its own bid.
+    Candle,
+    /// Direct registration of a name that is not auctioned, the buyer pays the price of its length tier.
+    Fixed,
+    /// Claim of a reserved or pre-allocated name by the account it is assigned to.
+    Reserved,
+}
+
+/// Schedule of the dutch auctions, the price falls linearly from `start_price` to `end_price`
+/// over `duration` blocks after the end of the week the name was on the market.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuctionConfig {
+    start_price: U128,
+    end_price: U128,
+    duration: BlockHeight,
+}
+
+/// Permission of a function call access key, the allowance is unlimited if not set and has to be above 0 otherwise.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FunctionCallPermission {
+    allowance: Option<U128>,
+    receiver_id: AccountId,
+    // methods the key can call, all of them if empty
+    method_names: Vec<String>,
+}
+
+/// Access key added on claim, a full access key if `permission` is not set.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountKey {
+    public_key: Base58PublicKey,
+    permission: Option<FunctionCallPermission>,
+}
+
+/// Contract deployed on claim, initialized with a call to `init_method_name` if set.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractSetup {
+    code: Base64VecU8,
+    init_method_name: Option<String>,
+    init_args: Option<Base64VecU8>,
+}
+
+/// Keys, contract and initial balance of a claimed account.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountSetup {
+    keys: Vec<AccountKey>,
+    contract: Option<ContractSetup>,
+    // transferred to the account, out of the bid the claimer doesn't pay and the attached deposit
+    initial_balance: Option<U128>,
+}
+
+impl AccountSetup {
+    /// Returns the setup of an account with `public_key` as its only, full access, key.
+    fn full_access(public_key: Base58PublicKey) -> Self {
+        Self { keys: vec![AccountKey { public_key, permission: None }], contract: None, initial_balance: None }
+    }
+
+    /// Returns true if the keys decode to valid ed25519 or secp256k1 public keys, if the function call keys have
+    /// valid receivers and method names and an allowance above 0 if set, and if the account gets at least a key or a contract.
+    fn is_valid(&self) -> bool {
+        if self.keys.is_empty() && self.contract.is_none() {
+            return false;
+        }
+        let are_keys_valid = self.keys.iter().all(|key| {
+            is_valid_public_key(&key.public_key.0) && key.permission.as_ref().is_none_or(|permission| {
+                permission.allowance.is_none_or(|allowance| allowance.0 > 0)
+                    && env::is_valid_account_id(permission.receiver_id.as_bytes())
+                    && permission.method_names.iter().all(|method_name| !method_name.is_empty() && !method_name.contains(','))
+            })
+        });
+        let is_contract_valid = self.contract.as_ref().is_none_or(|contract| {
+            !contract.code.0.is_empty() && contract.init_method_name.as_ref().is_none_or(|method_name| !method_name.is_empty())
+        });
+        are_keys_valid && is_contract_valid
+    }
+}
+
+/// Where the proceeds of the auctions paid with a fungible token go.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TokenProceeds {
+    /// The proceeds stay locked in the registrar.
+    Burn,
+    /// The proceeds are transferred to `account_id`.
+    Treasury { account_id: AccountId },
+}
+
+/// Fungible token accepted to reveal bids, the revealed amounts have to be at least `reserve_price`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenConfig {
+    reserve_price: U128,
+    proceeds: TokenProceeds,
+}
+
+/// `msg` of the `ft_transfer_call` revealing a bid, the transferred amount is the masked amount.
+/// The bid is revealed on behalf of `bidder_account_id` if set, the sender's otherwise.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenRevealMsg {
+    account_id: AccountId,
+    salt: String,
+    #[serde(default)]
+    bidder_account_id: Option<AccountId>,
+}
+
+/// Sealed bid signed off-chain by `bidder_account_id` with its bid key, submitted by anyone through `bid_signed`.
+/// The signed message is the borsh serialization of the payload. `registrar_id` is the account of the registrar
+/// and `nonce` has to be above the last nonce used by the bidder. The payload expires after the block `expiry`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BidPayload {
+    registrar_id: AccountId,
+    bidder_account_id: AccountId,
+    account_id: AccountId,
+    commitment: Vec<u8>,
+    nonce: u64,
+    expiry: BlockHeight,
+}
+
+/// Price of the direct registration of the names at least `min_length` characters long,
+/// up to the next tier.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceTier {
+    min_length: u64,
+    price: U128,
 }
 
 // AccountId of the bidder and AccountId of the revealer
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct Auction {
     start_block_height: BlockHeight,
+    kind: AuctionKind,
     bids: UnorderedMap<AccountId, Bid>,
     reveals: UnorderedMap<AccountId, Balance>,
+    claim_status: ClaimStatus,
+    standings: Standings,
+    // block height before which the bids of a candle auction count, picked once the bidding period is over
+    close_block_height: Option<BlockHeight>,
+    // hash chain of the random seeds of the blocks the candle bids were placed in, fixed once the bidding period is over
+    close_seed: Vec<u8>,
+    // balance transferred to the account on claim, refunded if the account creation fails
+    initial_balance: Balance,
+    // account the winner assigned the right to claim the name to
+    claim_assignee: Option<AccountId>,
+    // fungible token the bids are revealed with, set by the first bid, NEAR if not set
+    token_id: Option<AccountId>,
+    // block height the auction is listed under in `auctions_by_start`, kept when the auction is restarted
+    listed_block_height: Option<BlockHeight>,
+}
+
+impl Auction {
+    /// Creates an auction for `account_id`, the bids and reveals are stored under prefixes derived from `hash(account_id)`.
+    fn new(account_id: &AccountId, start_block_height: BlockHeight) -> Self {
+        let account_hash = env::sha256(account_id.as_bytes());
+        let mut bids_prefix = account_hash.clone();
+        bids_prefix.extend_from_slice(b":b");
+        let mut reveals_prefix = account_hash;
+        reveals_prefix.extend_from_slice(b":r");
+        Self {
+            start_block_height,
+            kind: AuctionKind::Sealed,
+            bids: UnorderedMap::new(bids_prefix),
+            reveals: UnorderedMap::new(reveals_prefix),
+            claim_status: ClaimStatus::Unclaimed,
+            standings: Standings::default(),
+            close_block_height: None,
+            close_seed: Vec::new(),
+            initial_balance: 0,
+            claim_assignee: None,
+            token_id: None,
+            listed_block_height: None,
+        }
+    }
+
+    /// Settles the auction over the revealed bids, the winner of an open auction pays its own bid.
+    /// A candle auction is settled once it is closed.
+    fn settlement(&self) -> Option<Settlement> {
+        if self.kind == AuctionKind::Candle && self.close_block_height.is_none() {
+            return None;
+        }
+        let settlement = self.standings.settlement()?;
+        match self.kind {
+            AuctionKind::Sealed => Some(settlement),
+            AuctionKind::Open | AuctionKind::Dutch | AuctionKind::Candle | AuctionKind::Fixed | AuctionKind::Reserved => Some(Settlement { price: settlement.highest_bid, ..settlement }),
+        }
+    }
+
+    /// Clears the auction if the account couldn't be created for the winner, so the name can be auctioned again.
+    fn reset_if_claim_failed(&mut self) {
+        if self.claim_status == ClaimStatus::Failed {
+            self.bids.clear();
+            self.reveals.clear();
+            self.standings = Standings::default();
+            self.close_block_height = None;
+            self.close_seed = Vec::new();
+            self.initial_balance = 0;
+            self.claim_assignee = None;
+            self.token_id = None;
+            self.start_block_height = 0;
+            self.kind = AuctionKind::Sealed;
+            self.claim_status = ClaimStatus::Unclaimed;
+        }
+    }
+
+    /// Returns the account the bid of `bidder_account_id` is placed for.
+    fn beneficiary(&self, bidder_account_id: &AccountId) -> AccountId {
+        self.bids
+            .get(bidder_account_id)
+            .and_then(|bid| bid.beneficiary_account_id)
+            .unwrap_or_else(|| bidder_account_id.to_string())
+    }
+
+    /// Returns the account the win belongs to, the beneficiary of the winning bid.
+    fn winning_beneficiary(&self) -> Option<AccountId> {
+        self.settlement().map(|settlement| self.beneficiary(&settlement.winning_account_id))
+    }
+
+    /// Returns true if `account_id` wins the auction over the revealed bids.
+    fn is_winner(&self, account_id: &AccountId) -> bool {
+        self.settlement().is_some_and(|settlement| &settlement.winning_account_id == account_id)
+    }
+}
+
+/// Outcome of a second-price auction.
+#[derive(PartialEq, Debug)]
+pub struct Settlement {
+    winning_account_id: AccountId,
+    highest_bid: Balance,
+    price: Balance,
+}
+
+/// Highest revealed bids of an auction, updated on every reveal so settlement doesn't have to go over all the reveals.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Standings {
+    // AccountId of the highest bidder and the block height of its bid
+    highest_bidder: Option<(AccountId, BlockHeight)>,
+    highest_bid: Balance,
+    second_highest_bid: Balance,
+}
+
+impl Standings {
+    /// Records the revealed `amount` of the bid placed by `account_id` at `block_height`.
+    /// Ties go to the earliest bid, then to the lowest account id, so the standings don't depend on the order of the reveals.
+    fn record(&mut self, account_id: &AccountId, amount: Balance, block_height: BlockHeight) {
+        if amount == 0 {
+            return;
+        }
+
+        let is_highest = match &self.highest_bidder {
+            None => true,
+            Some((highest_account_id, _)) if highest_account_id == account_id => return,
+            Some((highest_account_id, highest_block_height)) => {
+                amount > self.highest_bid
+                    || (amount == self.highest_bid
+                        && (block_height, account_id) < (*highest_block_height, highest_account_id))
+            }
+        };
+        if is_highest {
+            self.second_highest_bid = self.highest_bid;
+            self.highest_bid = amount;
+            self.highest_bidder = Some((account_id.to_string(), block_height));
+        } else if amount > self.second_highest_bid {
+            self.second_highest_bid = amount;
+        }
+    }
+
+    /// The highest bid wins and pays the second highest bid, or its own amount if no other bid is above 0.
+    /// Returns `None` if no bid is above 0.
+    fn settlement(&self) -> Option<Settlement> {
+        let (winning_account_id, _) = self.highest_bidder.as_ref()?;
+        let price = if self.second_highest_bid == 0 { self.highest_bid } else { self.second_highest_bid };
+        Some(Settlement { winning_account_id: winning_account_id.to_string(), highest_bid: self.highest_bid, price })
+    }
+}
+
+/// Returns the week since the launch of the auctions in which `account_id` is on the market, `hash(account_id) % 52`.
+fn auction_week(account_id: &AccountId) -> u64 {
+    // calculate account_id hash
+    let mut account_hasher = DefaultHasher::new();
+    account_hasher.write(account_id.as_bytes());
+    let account_hash = account_hasher.finish();  
+
+    account_hash % 52
+}
+
+/// Returns the block height at which a candle auction started at `start_block_height` closes, derived from `close_seed`.
+/// The close block height is in `(start_block_height, start_block_height + auction_period]`, so the first bid always counts.
+fn candle_close_block_height(start_block_height: BlockHeight, auction_period: BlockHeight, close_seed: &[u8]) -> BlockHeight {
+    let hash = env::sha256(close_seed);
+    let mut random_bytes = [0u8; 8];
+    random_bytes.copy_from_slice(&hash[..8]);
+    start_block_height + 1 + u64::from_le_bytes(random_bytes) % auction_period
+}
+
+/// Returns the key of the blind bid of `bidder_account_id` with `commitment`, `bidder_account_id:commitment`.
+fn blind_bid_key(bidder_account_id: &AccountId, commitment: &[u8]) -> String {
+    format!("{}:{}", bidder_account_id, String::from_utf8_lossy(commitment))
+}
+
+/// Returns true if `public_key` is an ed25519 or secp256k1 public key, prefixed by its curve, that decodes to a point of the curve.
+fn is_valid_public_key(public_key: &[u8]) -> bool {
+    match public_key.split_first() {
+        Some((0, key)) => key.len() == 32 && ed25519_dalek::PublicKey::from_bytes(key).is_ok(),
+        Some((1, key)) => libsecp256k1::PublicKey::parse_slice(key, Some(libsecp256k1::PublicKeyFormat::Raw)).is_ok(),
+        _ => false,
+    }
+}
+
+/// Returns the Merkle leaf pre-allocating `account_id` to `beneficiary_account_id`, `hash(account_id:beneficiary_account_id)`.
+fn allocation_leaf(account_id: &AccountId, beneficiary_account_id: &AccountId) -> Vec<u8> {
+    env::sha256(format!("{}:{}", account_id, beneficiary_account_id).as_bytes())
+}
+
+/// Returns the parent of two Merkle nodes, the nodes are sorted so that proofs don't need their positions.
+fn merkle_parent(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let (first, second) = if left <= right { (left, right) } else { (right, left) };
+    let mut nodes = first.to_vec();
+    nodes.extend_from_slice(second);
+    env::sha256(&nodes)
+}
+
+/// Returns true if `proof`, the siblings from `leaf` up to the root, leads to `root`.
+fn verify_merkle_proof(leaf: Vec<u8>, proof: &[Vec<u8>], root: &[u8]) -> bool {
+    proof.iter().fold(leaf, |node, sibling| merkle_parent(&node, sibling)) == root
+}
+
+/// Returns the key of the token balance of `account_id`, `:` isn't allowed in account ids.
+fn token_balance_key(token_id: &AccountId, account_id: &AccountId) -> String {
+    format!("{}:{}", token_id, account_id)
+}
+
+/// Phase of an auction, derived from the block height at which it started.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AuctionPhase {
+    /// Commitments are accepted.
+    Bidding,
+    /// Bidders reveal their masked amounts.
+    Reveal,
+    /// Reveal period expired, the auction can be claimed and losing bids withdrawn.
+    Closed,
+}
+
+/// Auction as returned by `list_auctions`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionView {
+    account_id: AccountId,
+    start_block_height: BlockHeight,
+    kind: AuctionKind,
+    phase: AuctionPhase,
+    num_bids: u64,
+    num_reveals: u64,
+    claim_status: ClaimStatus,
+    // set for candle auctions once closed
+    close_block_height: Option<BlockHeight>,
+    // set once the reveal period is over or all the bids were revealed
+    winning_account_id: Option<AccountId>,
+    // in the currency of the auction
+    price: Option<U128>,
+    claim_assignee: Option<AccountId>,
+    // fungible token the bids were revealed with, NEAR if not set
+    token_id: Option<AccountId>,
+}
+
+/// Bid as returned by `list_bids`. The commitment and amount are hidden while the auction is in the bidding phase.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BidView {
+    bidder_account_id: AccountId,
+    commitment: Option<String>,
+    amount: Option<U128>,
+}
+
+/// What a bidder has left to do in an auction they took part in.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ParticipationStatus {
+    /// The auction is still accepting bids.
+    Bidding,
+    /// The bid has to be revealed before the reveal period expires.
+    NeedsReveal,
+    /// The bid was revealed, waiting for the other bidders to reveal.
+    Revealed,
+    /// The bid is currently the highest, the auction can't be claimed yet.
+    Won,
+    /// The bid won and the name can be claimed.
+    Claimable,
+    /// The bid lost and its amount can be withdrawn.
+    Refundable,
+    /// The bid lost and its amount was withdrawn.
+    Withdrawn,
+    /// Nothing left to do, the bid was never revealed or the claim of the winner is over.
+    Done,
+}
+
+/// Token minted for a claimed name, the token id is the name.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct NameToken {
+    owner_id: AccountId,
+    // in the currency of the auction
+    price: Balance,
+    token_id: Option<AccountId>,
+    claim_block_height: BlockHeight,
+}
+
+/// NEP-171 token as returned by `nft_token`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    token_id: String,
+    owner_id: AccountId,
+    metadata: TokenMetadata,
+}
+
+/// NEP-177 token metadata, `extra` holds the price, its currency and the claim block height in JSON.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    title: Option<String>,
+    description: Option<String>,
+    media: Option<String>,
+    media_hash: Option<String>,
+    copies: Option<u64>,
+    issued_at: Option<String>,
+    expires_at: Option<String>,
+    starts_at: Option<String>,
+    updated_at: Option<String>,
+    extra: Option<String>,
+    reference: Option<String>,
+    reference_hash: Option<String>,
+}
+
+/// NEP-177 contract metadata as returned by `nft_metadata`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NFTContractMetadata {
+    spec: String,
+    name: String,
+    symbol: String,
+    icon: Option<String>,
+    base_uri: Option<String>,
+    reference: Option<String>,
+    reference_hash: Option<String>,
+}
+
+/// Participation as returned by `get_account_activity`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParticipationView {
+    account_id: AccountId,
+    status: ParticipationStatus,
 }
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize)]
 // AccountId that is auctioned
 pub struct Registrar {
+    owner_id: AccountId,
+    // parent account of the names in the sub-account mode, top level names if not set
+    suffix: Option<AccountId>,
     start_block_height: BlockHeight,
     auction_period: BlockHeight,
     reveal_period: BlockHeight,
-    auctions: UnorderedMap<AccountId, Auction>
+    // format of the auctions, unless set for the name in auction_kinds
+    auction_kind: AuctionKind,
+    auction_kinds: UnorderedMap<AccountId, AuctionKind>,
+    // minimum amount by which an open bid has to beat the highest bid
+    min_increment: Balance,
+    // fee attached to cancel a sealed bid during the bidding period
+    bid_cancel_fee: Balance,
+    // dutch auctions for names nobody bid on, disabled if not set
+    dutch_auction: Option<DutchAuctionConfig>,
+    // prices of the direct registration per name length, names without a tier can't be registered
+    register_prices: Vec<PriceTier>,
+    // AccountIds reserved by the owner and the AccountId each is assigned to, if any
+    reserved_names: UnorderedMap<AccountId, Option<AccountId>>,
+    // Merkle root of the pre-allocated (name, beneficiary) pairs, disabled if not set
+    allocation_root: Option<Vec<u8>>,
+    auctions: UnorderedMap<AccountId, Auction>,
+    // start block height and AccountId of the auctions, 0 for the names settled as soon as they are bought or claimed
+    auctions_by_start: TreeMap<(BlockHeight, AccountId), ()>,
+    // AccountId of the bidder and the AccountIds it bid on
+    participations: UnorderedMap<AccountId, UnorderedSet<AccountId>>,
+    // AccountId and the refunds that couldn't be transferred to it
+    balances: UnorderedMap<AccountId, Balance>,
+    // fungible tokens accepted to reveal bids
+    tokens: UnorderedMap<AccountId, TokenConfig>,
+    // `token_id:account_id` and the token refunds that couldn't be transferred to it
+    token_balances: UnorderedMap<String, Balance>,
+    // claimed AccountIds and their tokens
+    name_tokens: UnorderedMap<AccountId, NameToken>,
+    // AccountId of the token owner and the claimed AccountIds it owns
+    name_tokens_per_owner: UnorderedMap<AccountId, UnorderedSet<AccountId>>,
+    // AccountId and the ed25519 key it signs its bids with
+    bid_keys: UnorderedMap<AccountId, Vec<u8>>,
+    // AccountId and the last nonce of its signed bids, kept when the key changes
+    bid_nonces: UnorderedMap<AccountId, u64>,
+    // `bidder_account_id:commitment` of the blind bids not disclosed yet and the block they were placed at
+    blind_bids: UnorderedMap<String, BlindBid>,
+    // block height and `bidder_account_id:commitment` of the blind bids not disclosed yet, oldest first
+    blind_bids_by_block: TreeMap<(BlockHeight, String), ()>,
+    // AccountId and the balance it keeps in escrow to reveal its bids
+    escrow_balances: UnorderedMap<AccountId, Balance>,
 }
 
+impl Default for Registrar {
+    fn default() -> Self {
+        env::panic(b"Registrar should be initialized before usage")
+    }
+}
+
+#[near_bindgen]
 impl Registrar {  
     /// Construct this contract and record starting block height.
     /// auction_period represents the number of blocks an auction can take, aproximately 7 days
     /// reveal_period represents the number of blocks the reveal period can take, aproximately 7 days
+    #[init]
     pub fn new(auction_period: BlockHeight, reveal_period: BlockHeight) -> Self {
+        Self::new_with_auction_kind(auction_period, reveal_period, AuctionKind::Sealed, 0.into())
+    }
+
+    /// Construct this contract with `auction_kind` as the format of the auctions.
+    /// min_increment is the minimum amount by which an open bid has to beat the highest bid.
+    /// The account initializing the contract is its owner.
+    #[init]
+    pub fn new_with_auction_kind(auction_period: BlockHeight, reveal_period: BlockHeight, auction_kind: AuctionKind, min_increment: U128) -> Self {
+        Self::new_with_suffix(auction_period, reveal_period, auction_kind, min_increment, None)
+    }
+
+    /// Construct this contract to auction the names `label.suffix` if `suffix` is set, top level names otherwise.
+    /// Only the parent account can create sub-accounts, so `suffix` has to be the account the contract is deployed on.
+    #[init]
+    pub fn new_with_suffix(auction_period: BlockHeight, reveal_period: BlockHeight, auction_kind: AuctionKind, min_increment: U128, suffix: Option<AccountId>) -> Self {
+        if let Some(suffix) = &suffix {
+            assert_eq!(suffix, &env::current_account_id(), "The suffix should be the account of the registrar");
+        }
+        assert_ne!(auction_kind, AuctionKind::Dutch, "Dutch auctions are only for names nobody bid on");
+        assert_ne!(auction_kind, AuctionKind::Fixed, "Fixed prices are only for names that are not auctioned");
+        assert_ne!(auction_kind, AuctionKind::Reserved, "Reserved names are not auctioned");
         Self {
+            owner_id: env::predecessor_account_id(),
+            suffix,
             start_block_height: env::block_index(),
-            auction_period: auction_period,
-            reveal_period: reveal_period,
+            auction_period,
+            reveal_period,
+            auction_kind,
+            auction_kinds: UnorderedMap::new(b"k".to_vec()),
+            min_increment: min_increment.0,
+            bid_cancel_fee: 0,
+            dutch_auction: None,
+            register_prices: Vec::new(),
+            reserved_names: UnorderedMap::new(b"v".to_vec()),
+            allocation_root: None,
             auctions: UnorderedMap::new(b"a".to_vec()),
+            auctions_by_start: TreeMap::new(b"i".to_vec()),
+            participations: UnorderedMap::new(b"p".to_vec()),
+            balances: UnorderedMap::new(b"c".to_vec()),
+            tokens: UnorderedMap::new(b"t".to_vec()),
+            token_balances: UnorderedMap::new(b"f".to_vec()),
+            name_tokens: UnorderedMap::new(b"n".to_vec()),
+            name_tokens_per_owner: UnorderedMap::new(b"o".to_vec()),
+            bid_keys: UnorderedMap::new(b"e".to_vec()),
+            bid_nonces: UnorderedMap::new(b"x".to_vec()),
+            blind_bids: UnorderedMap::new(b"l".to_vec()),
+            blind_bids_by_block: TreeMap::new(b"m".to_vec()),
+            escrow_balances: UnorderedMap::new(b"s".to_vec()),
         }
     }
 
@@ -67,208 +694,430 @@ impl Registrar {
     /// Commitment is `hash(masked amount + salt)` in base58 encoding.
     /// bid fails if `account_id` is not yet on the market based on `hash(account_id) % 52 > weeks from start_blockhegiht`
     /// bid records a new auction if auction for this name doesn't exist yet.
-    /// bid fails if auction period expired.
+    /// bid fails if auction period expired, or if the auction takes bids in a fungible token, see `bid_with_token`.
+    /// bid fails if the bidder already bid on `account_id`, the bid can be replaced with `update_bid` or canceled with `cancel_bid`.
     pub fn bid(&mut self, account_id: AccountId, commitment: Vec<u8>) -> bool {
-        let new_bid = Bid {
-            amount: 0,
-            commitment: commitment
-        };
+        self.bid_with_beneficiary(account_id, commitment, None)
+    }
 
+    /// Bids like `bid`, on behalf of `beneficiary_account_id` if set.
+    /// The bidder pays and reveals the bid, and gets the refunds back. The win and the right to claim the name belong to the beneficiary.
+    pub fn bid_with_beneficiary(&mut self, account_id: AccountId, commitment: Vec<u8>, beneficiary_account_id: Option<AccountId>) -> bool {
         let bidder_account_id: AccountId = env::predecessor_account_id();
+        self.place_bid(&bidder_account_id, account_id, commitment, beneficiary_account_id, None)
+    }
 
-        // get the auction that match the account id, from the map
-        let start_block_height: BlockHeight = 0;
-        let account_hash = env::sha256(account_id.as_bytes());
-        let mut bids_prefix = account_hash.clone();
-        bids_prefix.extend_from_slice(b":b");
-        let bids: UnorderedMap<AccountId, Bid> = UnorderedMap::new(bids_prefix);
-        let mut reveals_prefix = account_hash;
-        reveals_prefix.extend_from_slice(b":r");
-        let reveals: UnorderedMap<AccountId, Balance> = UnorderedMap::new(reveals_prefix);
-        let empty_auction = Auction{ start_block_height, bids, reveals };
-        let mut auction = self.auctions.get(&account_id).unwrap_or(empty_auction);
-
-        // if there is an auction, insert the new bid to map
-        if auction.start_block_height != 0 {
-            // check if auction expired
-            let current_blockheight = env::block_index();
-            if current_blockheight - auction.start_block_height >= self.auction_period {
-                // restore the in-memory copy
-                self.auctions.insert(&account_id, &auction);
-                return false;
-            }
+    /// Bids like `bid`, the bid is revealed in the fungible token `token_id` through `ft_on_transfer`, or in NEAR if not set.
+    /// The first bid sets the currency of the auction. bid_with_token fails if `token_id` isn't accepted,
+    /// or if the auction already takes bids in another currency.
+    pub fn bid_with_token(&mut self, account_id: AccountId, commitment: Vec<u8>, token_id: Option<AccountId>) -> bool {
+        if token_id.as_ref().is_some_and(|token_id| self.tokens.get(token_id).is_none()) {
+            return false;
+        }
 
-            // if bidder already exists return false
-            let amount = 0;
-            let commitment: Vec<u8> = Vec::new();
-            let empty_bid: Bid = Bid { amount, commitment };
-            let bid = auction.bids.get(&bidder_account_id).unwrap_or(empty_bid); 
-            if bid.commitment.len() != 0 {
-                // restore the in-memory copy
-                self.auctions.insert(&account_id, &auction);
-                return false;
-            }
-   
-            // insert into bids map
-            auction.bids.insert(&bidder_account_id, &new_bid);
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        self.place_bid(&bidder_account_id, account_id, commitment, None, token_id)
+    }
 
-            // restore the in-memory copy
-            self.auctions.insert(&account_id, &auction);
-        } else {      
-            let current_blockheight = env::block_index();          
+    /// Replaces the commitment of the sealed bid of the caller on `account_id`, the bid counts as placed at the current block.
+    /// update_bid fails if the caller didn't bid on `account_id` or if the auction is past its bidding period.
+    pub fn update_bid(&mut self, account_id: AccountId, commitment: Vec<u8>) -> bool {
+        assert!(!commitment.is_empty(), "Commitment should not be empty");
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        let mut auction = match self.auctions.get(&account_id) {
+            Some(auction) if auction.kind == AuctionKind::Sealed && self.auction_phase(&auction) == AuctionPhase::Bidding => auction,
+            _ => return false,
+        };
+        let mut bid = match auction.bids.get(&bidder_account_id) {
+            Some(bid) => bid,
+            None => return false,
+        };
 
-            // calculate number of weeks until the auction started
-            let weeks = (current_blockheight - self.start_block_height) / self.auction_period;
+        bid.commitment = commitment;
+        bid.block_height = env::block_index();
+        auction.bids.insert(&bidder_account_id, &bid);
+        self.auctions.insert(&account_id, &auction);
+        true
+    }
 
-            // calculate account_id hash
-            let mut account_hasher = DefaultHasher::new();
-            account_hasher.write(account_id.as_bytes());
-            let account_hash = account_hasher.finish();  
+    /// Cancels the sealed bid of the caller on `account_id`, the attached deposit pays the cancel fee and the rest is refunded.
+    /// The auction is dropped along with its last bid, the name can then be bid on again while on the market.
+    /// cancel_bid fails if the caller didn't bid on `account_id`, if the auction is past its bidding period
+    /// or if the attached deposit doesn't cover the fee. The deposit of a failed cancel is refunded.
+    #[payable]
+    pub fn cancel_bid(&mut self, account_id: AccountId) -> bool {
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        let mut auction = match self.auctions.get(&account_id) {
+            Some(auction) if auction.kind == AuctionKind::Sealed && self.auction_phase(&auction) == AuctionPhase::Bidding => auction,
+            _ => return self.reject_deposit(),
+        };
+        let deposit = env::attached_deposit();
+        if deposit < self.bid_cancel_fee || auction.bids.remove(&bidder_account_id).is_none() {
+            return self.reject_deposit();
+        }
 
-            // check if account_id is open for auction
-            if weeks != account_hash % 52 {
-                return false;
+        if auction.bids.is_empty() {
+            self.auctions.remove(&account_id);
+            if let Some(block_height) = auction.listed_block_height {
+                self.auctions_by_start.remove(&(block_height, account_id.clone()));
             }
-
-            // insert this new auction to auction list
-            let mut new_auction = Auction {
-                                start_block_height: env::block_index(),
-                                bids: UnorderedMap::new(b"b".to_vec()),
-                                reveals: UnorderedMap::new(b"r".to_vec()),
-                            };
-            new_auction.bids.insert(&bidder_account_id, &new_bid);
-            self.auctions.insert(&account_id, &new_auction);       
+        } else {
+            self.auctions.insert(&account_id, &auction);
         }
-
-        return true;
+        self.remove_participation(&bidder_account_id, &account_id);
+        if deposit > self.bid_cancel_fee {
+            self.refund(&bidder_account_id, &bidder_account_id, deposit - self.bid_cancel_fee);
+        }
+        true
     }
 
-    /// Reveal shows the masked amount and salt. Invalid reveals are declined.
-    /// Reveal fails if auction is still going.
-    /// Reveal fails if `hash(masked_amount + salt)` != `commitment` by env::predeccessor_account_id()`
-    pub fn reveal(&mut self, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
-
-        // check if masked amount was deposited
-        if masked_amount != env::attached_deposit() {
+    /// Sets the fee attached to cancel a sealed bid, 0 to cancel for free. Only the owner can set the fee.
+    pub fn set_bid_cancel_fee(&mut self, bid_cancel_fee: U128) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
             return false;
         }
 
-        let revealer_account_id: AccountId = env::predecessor_account_id();
-        
-        // get the auction that match the account id, from the map
-        let start_block_height: BlockHeight = 0;
-        let account_hash = env::sha256(account_id.as_bytes());
-        let mut bids_prefix = account_hash.clone();
-        bids_prefix.extend_from_slice(b":b");
-        let bids: UnorderedMap<AccountId, Bid> = UnorderedMap::new(bids_prefix);
-        let mut reveals_prefix = account_hash;
-        reveals_prefix.extend_from_slice(b":r");
-        let reveals: UnorderedMap<AccountId, Balance> = UnorderedMap::new(reveals_prefix);
-        let empty_auction = Auction{ start_block_height, bids, reveals };
-        let mut auction = self.auctions.get(&account_id).unwrap_or(empty_auction);
+        self.bid_cancel_fee = bid_cancel_fee.0;
+        true
+    }
 
-        // insert into reaveals map if it matches the commitment
-        if auction.start_block_height != 0 {
-            // check if auction is in progress or if reveal period expired
-            let current_blockheight = env::block_index();
-            if (current_blockheight - auction.start_block_height < self.auction_period) || 
-               (current_blockheight - auction.start_block_height >= self.auction_period + self.reveal_period) {
-                   // restore the in-memory copy
-                   self.auctions.insert(&account_id, &auction);              
-                   return false;
-            }
+    /// Returns the fee attached to cancel a sealed bid.
+    pub fn get_bid_cancel_fee(&self) -> U128 {
+        self.bid_cancel_fee.into()
+    }
 
-            // check if `hash(masked_amount + salt)` != `commitment` by env::predeccessor_account_id()`
-            // if bidder already exists return false
-            let amount = 0;
-            let commitment: Vec<u8> = Vec::new();
-            let empty_bid: Bid = Bid { amount, commitment };
-            let mut bid = auction.bids.get(&revealer_account_id).unwrap_or(empty_bid);
-            if bid.commitment.len() != 0 {
-                // calculate hash(masked_amount + salt)
-                let commitment_hash = masked_amount.to_string() + &salt;
-                let revealer_commitment = &bs58::encode(&commitment_hash).into_string();
-                if str::from_utf8(&bid.commitment).unwrap() != revealer_commitment {
-                    // restore the in-memory copy
-                    auction.bids.insert(&revealer_account_id, &bid);
-                    self.auctions.insert(&account_id, &auction);
-                    return false;
-                }
+    /// Bids like `bid` on behalf of the bidder that signed `payload`, so that a relayer pays the gas.
+    /// `signature` is the ed25519 signature of the borsh serialized payload by the bid key of the bidder.
+    /// bid_signed fails if the payload is for another registrar or expired, if the bidder has no bid key,
+    /// if the nonce isn't above the last one of the bidder or if the signature is invalid.
+    /// The nonce is used up once the signature is verified, even if the bid itself fails.
+    pub fn bid_signed(&mut self, payload: BidPayload, signature: Base64VecU8) -> bool {
+        if payload.registrar_id != env::current_account_id() || payload.expiry < env::block_index() {
+            return false;
+        }
+        let public_key = match self.bid_keys.get(&payload.bidder_account_id) {
+            Some(public_key) => public_key,
+            None => return false,
+        };
+        if payload.nonce <= self.bid_nonces.get(&payload.bidder_account_id).unwrap_or(0) || signature.0.len() != 64 {
+            return false;
+        }
 
-                // set the missing bid amount info
-                bid.amount = masked_amount;
+        let message = payload.try_to_vec().unwrap_or_else(|_| env::panic(b"Failed to serialize the payload"));
+        let verified = match (ed25519_dalek::PublicKey::from_bytes(&public_key), ed25519_dalek::Signature::try_from(&signature.0[..])) {
+            (Ok(key), Ok(sig)) => key.verify_strict(&message, &sig).is_ok(),
+            _ => false,
+        };
+        if !verified {
+            return false;
+        }
 
-                // restore the in-memory bid copy
-                auction.bids.insert(&revealer_account_id, &bid);
-            } else {
-                // restore the in-memory bid copy
-                self.auctions.insert(&account_id, &auction);
-                return false;
-            }
-            
-            // insert into reveal's map
-            auction.reveals.insert(&revealer_account_id, &masked_amount);
+        self.bid_nonces.insert(&payload.bidder_account_id, &payload.nonce);
+        self.place_bid(&payload.bidder_account_id, payload.account_id, payload.commitment, None, None)
+    }
 
-            // restore the in-memory copy
-            self.auctions.insert(&account_id, &auction);
-        } else {
+    /// Registers the ed25519 `public_key` the caller signs its bids with, replacing the previous one.
+    /// The nonces of the caller carry over. Register fails if the key isn't a valid ed25519 key.
+    pub fn register_bid_key(&mut self, public_key: Base58PublicKey) -> bool {
+        if public_key.0.len() != 33 || public_key.0[0] != 0 || ed25519_dalek::PublicKey::from_bytes(&public_key.0[1..]).is_err() {
             return false;
         }
 
-        return true;
+        self.bid_keys.insert(&env::predecessor_account_id(), &public_key.0[1..].to_vec());
+        true
     }
 
-    /// Withdraw funds for loosing bids.
-    /// Withdraw fails if account_id doesn't exist, if `env::predeccessor_account_id()` didn't bid or if auction is still in progress or not all bids were revealed yet.
-    /// If not all bids were revealed but required reveal period passed, can withdraw.
-    pub fn withdraw(&mut self, account_id: AccountId) -> bool {
+    /// Removes the bid key of the caller, its signed bids are no longer accepted.
+    pub fn remove_bid_key(&mut self) -> bool {
+        self.bid_keys.remove(&env::predecessor_account_id()).is_some()
+    }
 
-        let withdrawer_account_id: AccountId = env::predecessor_account_id();
+    /// Returns the bid key of `account_id`, if any.
+    pub fn get_bid_key(&self, account_id: AccountId) -> Option<Base58PublicKey> {
+        self.bid_keys.get(&account_id).map(|public_key| {
+            let mut bytes = vec![0];
+            bytes.extend(public_key);
+            Base58PublicKey(bytes)
+        })
+    }
 
-        // get the auction that match the account id, from the map
-        let start_block_height: BlockHeight = 0;
-        let account_hash = env::sha256(account_id.as_bytes());
-        let mut bids_prefix = account_hash.clone();
-        bids_prefix.extend_from_slice(b":b");
-        let bids: UnorderedMap<AccountId, Bid> = UnorderedMap::new(bids_prefix);
-        let mut reveals_prefix = account_hash;
-        reveals_prefix.extend_from_slice(b":r");
-        let reveals: UnorderedMap<AccountId, Balance> = UnorderedMap::new(reveals_prefix);
-        let empty_auction = Auction{ start_block_height, bids, reveals };
-        let mut auction = self.auctions.get(&account_id).unwrap_or(empty_auction);
+    /// Returns the last nonce of the signed bids of `account_id`, 0 if it has none.
+    pub fn get_bid_nonce(&self, account_id: AccountId) -> u64 {
+        self.bid_nonces.get(&account_id).unwrap_or(0)
+    }
 
-        // withdraw funds for loosing bider
-        if auction.start_block_height != 0 {
-            // return false if the auction is in progress 
-            let current_blockheight = env::block_index();
-            if current_blockheight - auction.start_block_height < self.auction_period {
-                // restore the in-memory copy
-                self.auctions.insert(&account_id, &auction);
-                return false;
-            }
+    /// Places a sealed bid without naming the account, the name is disclosed by `reveal_blind`.
+    /// Commitment is `sha256(account_id + ":" + masked amount + ":" + salt)` in base58 encoding.
+    /// The attached deposit pays for the storage of the blind bid, it is refunded once the bid is disclosed or expired.
+    /// bid_blind fails if the caller already placed a blind bid with this commitment or if the deposit doesn't cover the storage.
+    /// The deposit of a failed bid is refunded.
+    /// While a blind bid is undisclosed, the sealed auctions of the names on the market at its block are only settled
+    /// at the end of their reveal period.
+    #[payable]
+    pub fn bid_blind(&mut self, commitment: Vec<u8>) -> bool {
+        self.remove_expired_blind_bids(EXPIRED_BLIND_BIDS_LIMIT);
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        let key = blind_bid_key(&bidder_account_id, &commitment);
+        if self.blind_bids.get(&key).is_some() {
+            return self.reject_deposit();
+        }
 
-            // return false if reveal is in progress and not all bidders revealed themselves
-            if current_blockheight - auction.start_block_height < self.auction_period + self.reveal_period {
-                if auction.bids.len() != auction.reveals.len() {
-                    // restore the in-memory copy
-                    self.auctions.insert(&account_id, &auction);
-                    return false;
+        let storage_usage = env::storage_usage();
+        let block_height = env::block_index();
+        let deposit = env::attached_deposit();
+        self.blind_bids.insert(&key, &BlindBid { bidder_account_id, block_height, deposit });
+        self.blind_bids_by_block.insert(&(block_height, key.clone()), &());
+        if Balance::from(env::storage_usage() - storage_usage) * STORAGE_PRICE_PER_BYTE > deposit {
+            self.blind_bids.remove(&key);
+            self.blind_bids_by_block.remove(&(block_height, key));
+            return self.reject_deposit();
+        }
+        true
+    }
+
+    /// Discloses the name of a blind bid and reveals it like `reveal`, attaching the masked amount.
+    /// The bid joins the sealed auction of `account_id` as if it was placed at the block of the blind bid, it starts
+    /// the auction at that block if there is none. The name has to be on the market at that block, and the blind bid
+    /// has to be placed before the end of the bidding period of the auction.
+    /// Reveal fails until the auction is in the reveal period, or if the name was already claimed or is being claimed.
+    /// A blind bid that turns out ineligible is dropped. The deposit of a failed reveal is refunded.
+    #[payable]
+    pub fn reveal_blind(&mut self, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        if masked_amount != env::attached_deposit() {
+            return self.reject_deposit();
+        }
+
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        if !self.disclose_blind_bid(&bidder_account_id, account_id, masked_amount, salt) {
+            return self.reject_deposit();
+        }
+        true
+    }
+
+    /// Deposits the attached amount to the escrow balance of the caller, bids revealed from the escrow draw from it.
+    /// Deposit fails if nothing is attached.
+    #[payable]
+    pub fn deposit_escrow(&mut self) -> bool {
+        let account_id: AccountId = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        if amount == 0 {
+            return false;
+        }
+
+        let balance = self.escrow_balances.get(&account_id).unwrap_or(0);
+        self.escrow_balances.insert(&account_id, &(balance + amount));
+        true
+    }
+
+    /// Transfers `amount` of the escrow balance of the caller back to it.
+    /// Withdraw fails if `amount` is 0 or above the escrow balance.
+    pub fn withdraw_escrow(&mut self, amount: U128) -> bool {
+        let account_id: AccountId = env::predecessor_account_id();
+        if amount.0 == 0 || !self.draw_escrow(&account_id, amount.0) {
+            return false;
+        }
+
+        self.refund(&account_id, &account_id, amount.0);
+        true
+    }
+
+    /// Returns the escrow balance of `account_id`.
+    pub fn get_escrow_balance(&self, account_id: AccountId) -> U128 {
+        self.escrow_balances.get(&account_id).unwrap_or(0).into()
+    }
+
+    /// Reveals like `reveal`, the masked amount is drawn from the escrow balance of the caller instead of being attached.
+    /// Reveal fails if the escrow balance is below the masked amount. The amount of a failed reveal stays in the escrow.
+    /// If the bid was already revealed nothing is drawn and reveal succeeds.
+    pub fn reveal_from_escrow(&mut self, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        self.reveal_for_from_escrow(bidder_account_id, account_id, masked_amount, salt)
+    }
+
+    /// Reveals the bid of `bidder_account_id` like `reveal_for`, the masked amount is drawn from the escrow balance of the bidder.
+    /// The escrow locks the funds of the bidder ahead of the reveal, so the caller only needs the salt and attaches nothing.
+    /// The amount of a failed reveal stays in the escrow. If the bid was already revealed nothing is drawn and reveal succeeds.
+    pub fn reveal_for_from_escrow(&mut self, bidder_account_id: AccountId, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        if self.is_revealed(&account_id, &bidder_account_id) {
+            return true;
+        }
+        if !self.draw_escrow(&bidder_account_id, masked_amount) {
+            return false;
+        }
+
+        if !self.reveal_bid(&account_id, &bidder_account_id, masked_amount, salt, None) {
+            self.credit_escrow(&bidder_account_id, masked_amount);
+            return false;
+        }
+        true
+    }
+
+    /// Discloses and reveals a blind bid like `reveal_blind`, the masked amount is drawn from the escrow balance of the caller.
+    /// The amount of a failed reveal stays in the escrow.
+    pub fn reveal_blind_from_escrow(&mut self, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+        if !self.draw_escrow(&bidder_account_id, masked_amount) {
+            return false;
+        }
+
+        if !self.disclose_blind_bid(&bidder_account_id, account_id, masked_amount, salt) {
+            self.credit_escrow(&bidder_account_id, masked_amount);
+            return false;
+        }
+        true
+    }
+
+    /// Bid in an open or candle auction, the attached deposit is the public amount of the bid.
+    /// bid_open fails if `account_id` is not auctioned in the open or candle format or is not yet on the market, if auction period expired
+    /// or if the attached deposit doesn't beat the highest bid by `min_increment`. The deposit of a failed bid is refunded.
+    /// In an open auction the outbid amount is refunded right away. In a candle auction each bidder bids once,
+    /// the outbid amounts are withdrawn once the auction is closed since they might have been placed before the close block.
+    #[payable]
+    pub fn bid_open(&mut self, account_id: AccountId) -> bool {
+        let amount = env::attached_deposit();
+        let bidder_account_id: AccountId = env::predecessor_account_id();
+
+        // get the auction that match the account id, from the map
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+        auction.reset_if_claim_failed();
+
+        if auction.start_block_height == 0 {
+            // check if account_id is open for an open or candle auction
+            let auction_kind = self.get_auction_kind(account_id.to_string());
+            if amount == 0 || !self.is_open_for_auction(&account_id) || (auction_kind != AuctionKind::Open && auction_kind != AuctionKind::Candle) {
+                return self.reject_deposit();
+            }
+            auction.start_block_height = env::block_index();
+            auction.kind = auction_kind;
+            self.list_auction(&account_id, &mut auction);
+        } else if (auction.kind != AuctionKind::Open && auction.kind != AuctionKind::Candle)
+            || self.auction_phase(&auction) != AuctionPhase::Bidding
+            || amount < auction.standings.highest_bid + std::cmp::max(self.min_increment, 1)
+            || (auction.kind == AuctionKind::Candle && auction.bids.get(&bidder_account_id).is_some()) {
+            return self.reject_deposit();
+        }
+
+        // refund the outbid amount right away
+        if auction.kind == AuctionKind::Candle {
+            // every candle bid is kept until the auction is closed
+        } else if let Some((highest_account_id, _)) = auction.standings.highest_bidder.clone() {
+            if let Some(outbid) = auction.bids.remove(&highest_account_id) {
+                if outbid.amount > 0 {
+                    self.refund(&highest_account_id, &highest_account_id, outbid.amount);
                 }
             }
+            auction.reveals.remove(&highest_account_id);
+            self.remove_participation(&highest_account_id, &account_id);
+        }
+
+        // every candle bid commits the seed of its block to the close block height
+        if auction.kind == AuctionKind::Candle {
+            auction.close_seed = env::sha256(&[auction.close_seed.as_slice(), env::random_seed().as_slice()].concat());
+        }
+
+        // the open bid is revealed right away
+        let block_height = env::block_index();
+        auction.bids.insert(&bidder_account_id, &Bid { amount, commitment: Vec::new(), block_height, beneficiary_account_id: None });
+        auction.reveals.insert(&bidder_account_id, &amount);
+        auction.standings = Standings {
+            highest_bidder: Some((bidder_account_id.to_string(), block_height)),
+            highest_bid: amount,
+            second_highest_bid: auction.standings.highest_bid,
+        };
+
+        self.auctions.insert(&account_id, &auction);
+        self.add_participation(&bidder_account_id, &account_id);
+        true
+    }
+
+    /// Reveal shows the masked amount and salt. Invalid reveals are declined.
+    /// Reveal fails if auction is still going, or if the name was already claimed or is being claimed.
+    /// Reveal fails if `hash(masked_amount + salt)` != `commitment` by env::predeccessor_account_id()`
+    /// The deposit of a failed reveal is refunded. If the bid was already revealed the deposit is refunded and reveal succeeds.
+    #[payable]
+    pub fn reveal(&mut self, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+
+        // check if masked amount was deposited
+        if masked_amount != env::attached_deposit() {
+            return self.reject_deposit();
+        }
+
+        let revealer_account_id: AccountId = env::predecessor_account_id();
+        if self.is_revealed(&account_id, &revealer_account_id) {
+            self.reject_deposit();
+            return true;
+        }
+        if !self.reveal_bid(&account_id, &revealer_account_id, masked_amount, salt, None) {
+            return self.reject_deposit();
+        }
+        true
+    }
+
+    /// Reveals the bid of `bidder_account_id` like `reveal`, the caller holds the salt and attaches the masked amount.
+    /// The revealed amount belongs to the bidder, it gets the refunds. The deposit of a failed reveal is refunded to the caller.
+    /// If the bid was already revealed, by the bidder or another caller, the deposit is refunded and reveal_for succeeds.
+    #[payable]
+    pub fn reveal_for(&mut self, bidder_account_id: AccountId, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        if masked_amount == env::attached_deposit() && self.is_revealed(&account_id, &bidder_account_id) {
+            self.reject_deposit();
+            return true;
+        }
+        if masked_amount != env::attached_deposit() || !self.reveal_bid(&account_id, &bidder_account_id, masked_amount, salt, None) {
+            return self.reject_deposit();
+        }
+        true
+    }
+
+    /// Withdraw funds for loosing bids.
+    /// Withdraw fails if account_id doesn't exist, if `env::predeccessor_account_id()` didn't bid or if auction is still in progress or not all bids were revealed yet.
+    /// If not all bids were revealed but required reveal period passed, can withdraw.
+    pub fn withdraw(&mut self, account_id: AccountId) -> bool {
+
+        let withdrawer_account_id: AccountId = env::predecessor_account_id();
+
+        // get the auction that match the account id, from the map
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+
+        // withdraw funds for loosing bider
+        if auction.start_block_height != 0 {
+            // return false if the auction is in progress 
+            let current_blockheight = env::block_index();
+            if current_blockheight - auction.start_block_height < self.auction_period {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+                return false;
+            }
+
+            // return false if reveal is in progress and not all bidders revealed themselves
+            if current_blockheight - auction.start_block_height < self.auction_period + self.reveal_period
+                && !self.is_fully_revealed(&auction) {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+                return false;
+            }
+
+            // return false for the winner, its bid is paid on claim and refunded by `on_claim` if the claim fails
+            self.close_candle(&mut auction);
+            if auction.claim_status != ClaimStatus::Failed && auction.is_winner(&withdrawer_account_id) {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+                return false;
+            }
 
-            // withdraw funds for loosing bider
+            // withdraw funds for loosing bider, candle bids have no commitment
+            let is_candle_bid = auction.kind == AuctionKind::Candle && auction.bids.get(&withdrawer_account_id).is_some();
             let amount = 0;
             let commitment: Vec<u8> = Vec::new();
-            let empty_bid: Bid = Bid { amount, commitment };
+            let empty_bid: Bid = Bid { amount, commitment, block_height: 0, beneficiary_account_id: None };
             let mut bid = auction.bids.get(&withdrawer_account_id).unwrap_or(empty_bid); 
-            if bid.commitment.len() != 0 {
+            if !bid.commitment.is_empty() || is_candle_bid {
                 // transfer back the bid.amount
                 if bid.amount > 0 {
-                    Promise::new(withdrawer_account_id.to_string()).transfer(bid.amount);
+                    self.refund_bid(&auction, &withdrawer_account_id, bid.amount);
                     bid.amount = 0;    
                 }
 
-                // restore the in-memory bid copy
+                // restore the in-memory bid copy, the participation stays with the bid marked as withdrawn
                 auction.bids.insert(&withdrawer_account_id, &bid);
             } else {
                 // restore the in-memory copy
@@ -282,197 +1131,1564 @@ impl Registrar {
             return false;
         }
 
-        return true;
+        true
     }
 
-    /// Creates the new name with given public key for the winer.
-    /// The winner of the auction pays the second-highest price.
-    pub fn claim(&mut self, account_id: AccountId, public_key: Base58PublicKey) -> bool {
-        // get the auction that match the account id, from the map
-        let start_block_height: BlockHeight = 0;
-        let account_hash = env::sha256(account_id.as_bytes());
-        let mut bids_prefix = account_hash.clone();
-        bids_prefix.extend_from_slice(b":b");
-        let bids: UnorderedMap<AccountId, Bid> = UnorderedMap::new(bids_prefix);
-        let mut reveals_prefix = account_hash;
-        reveals_prefix.extend_from_slice(b":r");
-        let reveals: UnorderedMap<AccountId, Balance> = UnorderedMap::new(reveals_prefix);
-        let empty_auction = Auction{ start_block_height, bids, reveals };
-        let mut auction = self.auctions.get(&account_id).unwrap_or(empty_auction);
+    /// Withdraw funds for all the loosing bids of `env::predeccessor_account_id()` in a single transfer.
+    /// At most `limit` of the auctions the caller bid on are looked at, auctions that are still in progress, won or already
+    /// withdrawn from are skipped. The auctions whose claim is over are forgotten, so the next call looks at the others.
+    /// Withdraw fails if no auction could be withdrawn from.
+    pub fn withdraw_all(&mut self, limit: u64) -> bool {
+        let withdrawer_account_id: AccountId = env::predecessor_account_id();
 
-        // withdraw funds for loosing bider
-        if auction.start_block_height != 0 {
-            // check if auction is in progress
-            let current_blockheight = env::block_index();
-            if current_blockheight - auction.start_block_height < self.auction_period {
-                // restore the in-memory copy
+        let auction_ids: Vec<AccountId> = match self.participations.get(&withdrawer_account_id) {
+            Some(auction_ids) => auction_ids.iter().take(limit as usize).collect(),
+            None => return false,
+        };
+
+        let mut total_amount: Balance = 0;
+        let mut withdrawn: u64 = 0;
+        for account_id in auction_ids {
+            let mut auction = match self.auctions.get(&account_id) {
+                Some(auction) => auction,
+                None => {
+                    self.remove_participation(&withdrawer_account_id, &account_id);
+                    continue;
+                }
+            };
+            if self.close_candle(&mut auction) {
                 self.auctions.insert(&account_id, &auction);
-                return false;
+            }
+            if !self.is_settled(&auction) {
+                continue;
             }
 
-            // check if reaveal is in progress 
-            if current_blockheight - auction.start_block_height < self.auction_period + self.reveal_period {
-                // check if all bidders revealed themselves
-                if auction.bids.len() != auction.reveals.len() {
-                    // restore the in-memory copy
-                    self.auctions.insert(&account_id, &auction);
-                    return false;
+            // the winning bid is paid on claim
+            if auction.is_winner(&withdrawer_account_id) {
+                continue;
+            }
+
+            // bids already withdrawn or never revealed have nothing to withdraw
+            if let Some(mut bid) = auction.bids.get(&withdrawer_account_id).filter(|bid| bid.amount > 0) {
+                // token bids are transferred back one by one
+                if auction.token_id.is_some() {
+                    self.refund_bid(&auction, &withdrawer_account_id, bid.amount);
+                } else {
+                    total_amount += bid.amount;
                 }
+                bid.amount = 0;
+                auction.bids.insert(&withdrawer_account_id, &bid);
+                self.auctions.insert(&account_id, &auction);
+                withdrawn += 1;
             }
 
-            // get the second highest bid
-            let mut winning_account_id: AccountId = "".to_string();
-            let mut second_highest_bid: Balance = 0;
-            let mut highest_bid: Balance = 0;
-            let mut is_first_check: bool = true;
+            // nothing is left to do once the claim of the winner is over
+            if auction.claim_status == ClaimStatus::Claimed {
+                self.remove_participation(&withdrawer_account_id, &account_id);
+            }
+        }
 
-            let reveals = auction.reveals.iter();
-            for (revealer_account_id, revealer_balance) in reveals {
-                // set the highest_bid as the first map entry
-                if is_first_check {
-                    highest_bid = revealer_balance;
-                    is_first_check = false;
-                    winning_account_id = revealer_account_id.to_string();
-                    continue;
-                }
+        if total_amount == 0 {
+            return withdrawn > 0;
+        }
 
-                if revealer_balance > second_highest_bid {
-                    second_highest_bid = revealer_balance;
+        // transfer back the total amount
+        self.refund(&withdrawer_account_id, &withdrawer_account_id, total_amount);
 
-                    if highest_bid < second_highest_bid {
-                        let temp = highest_bid;
-                        highest_bid = second_highest_bid;
-                        second_highest_bid = temp;
-                        winning_account_id = revealer_account_id.to_string();
-                    }                     
-                }
-            }
-            
-            // if second_highest_bid is 0 and highest_bid is greater, then second_highest_bid takes the value of highest_bid
-            if second_highest_bid == 0 {
-                // if second_highest_bid and highest_bid are 0, return false
-                if highest_bid == 0 {
-                    // restore the in-memory copy
-                    self.auctions.insert(&account_id, &auction);
-                    return false;
-                }   
-                second_highest_bid = highest_bid;
-            }
+        true
+    }
 
-            // check if the claimer is also the winner
-            let claimer_account_id: AccountId = env::predecessor_account_id();
-            if winning_account_id != claimer_account_id {
-                // restore the in-memory copy
-                self.auctions.insert(&account_id, &auction);
-                return false;
-            }
+    /// Creates the new name with given public key for the winer.
+    /// The winner of the auction pays the second-highest price.
+    /// Claim fails if the name was already claimed or is being claimed, the outcome is checked by `on_claim`.
+    pub fn claim(&mut self, account_id: AccountId, public_key: Base58PublicKey) -> bool {
+        self.claim_account(&account_id, AccountSetup::full_access(public_key), 0)
+    }
 
-            // TODO: burn the second_highest_bid
+    /// Claims the name like `claim`, creating it with the keys, the contract and the initial balance of `setup`.
+    /// The initial balance is paid out of the bid the winner doesn't pay first, then out of the attached deposit,
+    /// what is left of both is refunded.
+    /// claim_with_setup also fails if a key isn't a valid ed25519 or secp256k1 public key, if a function call key
+    /// has an invalid receiver or method name, if there is neither a key nor a contract, or if the initial balance
+    /// is above what the claimer can pay. The deposit of a failed claim is refunded.
+    #[payable]
+    pub fn claim_with_setup(&mut self, account_id: AccountId, setup: AccountSetup) -> bool {
+        if !setup.is_valid() || !self.claim_account(&account_id, setup, env::attached_deposit()) {
+            return self.reject_deposit();
+        }
+        true
+    }
 
-            // creates the new name with given public key for the winer
-            let key = Base58PublicKey::from(public_key);
-            let p1 = Promise::new(account_id.to_string()).create_account();
-            let p2 = Promise::new(account_id.to_string()).add_full_access_key(key.0);
-            p1.then(p2);
-            
-            // get the vector of bidder_account_id
-            let bids = auction.bids.iter();
-            let mut bidders: Vec<AccountId> = Vec::new();
-            for (bidder_account_id, _bid) in bids {
-                if winning_account_id != bidder_account_id {
-                    bidders.push(bidder_account_id);
-                }
-            }
+    /// Callback for the account creation started by `claim`.
+    /// If the account was created the auction is done, otherwise the winner is refunded and the name can be auctioned again.
+    pub fn on_claim(&mut self, account_id: AccountId, winning_account_id: AccountId) -> bool {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Callback can only be called by the registrar");
 
-            // withdraw all other bids automatically
-            for bidder_account_id in bidders {
-                let amount = 0;
-                let commitment: Vec<u8> = Vec::new();
-                let empty_bid: Bid = Bid { amount, commitment };
-                let mut bid = auction.bids.get(&bidder_account_id).unwrap_or(empty_bid); 
-                if bid.commitment.len() != 0 {
-                    // transfer back the bid.amount
-                    if bid.amount > 0 {
-                        Promise::new(bidder_account_id.to_string()).transfer(bid.amount);
-                        bid.amount = 0;    
-                    }
+        let mut auction = match self.auctions.get(&account_id) {
+            Some(auction) => auction,
+            None => return false,
+        };
+        if auction.claim_status != ClaimStatus::Pending {
+            return false;
+        }
 
-                    // restore the in-memory bid copy
-                    auction.bids.insert(&bidder_account_id, &bid);
+        let is_created = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if is_created {
+            auction.claim_status = ClaimStatus::Claimed;
+            env::log(format!("{} claimed {}", winning_account_id, account_id).as_bytes());
+
+            // mint the token of the name for the claimer
+            let claimer_account_id = auction.claim_assignee.clone().unwrap_or_else(|| auction.beneficiary(&winning_account_id));
+            let name_token = NameToken {
+                owner_id: claimer_account_id,
+                price: auction.settlement().map_or(0, |settlement| settlement.price),
+                token_id: auction.token_id.clone(),
+                claim_block_height: env::block_index(),
+            };
+            self.mint_name_token(&account_id, name_token);
+
+            // the proceeds of a token auction go to the treasury of the token, or stay locked
+            let token_config = auction.token_id.as_ref().and_then(|token_id| self.tokens.get(token_id));
+            if let (Some(TokenConfig { proceeds: TokenProceeds::Treasury { account_id: treasury_id }, .. }), Some(bid)) = (token_config, auction.bids.get(&winning_account_id)) {
+                if bid.amount > 0 {
+                    self.refund_bid(&auction, &treasury_id, bid.amount);
                 }
             }
-
-            // restore the in-memory copy
-            self.auctions.insert(&account_id, &auction);
         } else {
-            return false;
+            auction.claim_status = ClaimStatus::Failed;
+            if let Some(mut bid) = auction.bids.get(&winning_account_id) {
+                if bid.amount > 0 {
+                    self.refund_bid(&auction, &winning_account_id, bid.amount);
+                }
+                env::log(format!("Failed to create {} for {}, refunded {}", account_id, winning_account_id, bid.amount).as_bytes());
+                bid.amount = 0;
+                auction.bids.insert(&winning_account_id, &bid);
+            }
+
+            // the transfer of the initial balance failed with the account creation, it was funded by the claimer
+            if auction.initial_balance > 0 {
+                let claimer_account_id = auction.claim_assignee.clone().unwrap_or_else(|| auction.beneficiary(&winning_account_id));
+                self.refund(&claimer_account_id, &claimer_account_id, auction.initial_balance);
+                env::log(format!("Refunded the initial balance {} of {} to {}", auction.initial_balance, account_id, claimer_account_id).as_bytes());
+                auction.initial_balance = 0;
+            }
         }
 
-        return true;
+        self.auctions.insert(&account_id, &auction);
+        self.remove_participation(&winning_account_id, &account_id);
+        is_created
     }
-}
 
+    /// Callback for the refund transfers.
+    /// If the transfer failed the amount is credited to the balance of `account_id`, to be withdrawn with `withdraw_balance`.
+    pub fn on_refund(&mut self, account_id: AccountId, amount: U128) -> bool {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Callback can only be called by the registrar");
 
-#[cfg(not(target_arch = "wasm32"))]
-#[cfg(test)]
-mod tests {
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            return true;
+        }
 
-    use super::*;
+        let balance = self.balances.get(&account_id).unwrap_or(0);
+        self.balances.insert(&account_id, &(balance + amount.0));
+        env::log(format!("Failed to refund {} to {}, credited to its balance", amount.0, account_id).as_bytes());
+        false
+    }
 
-    fn alice() -> AccountId {
-        "alice.near".to_string()
+    /// Transfers the balance of `env::predeccessor_account_id()` left by failed refunds to `receiver_id`.
+    /// Withdraw fails if the balance is 0.
+    pub fn withdraw_balance(&mut self, receiver_id: AccountId) -> bool {
+        let account_id: AccountId = env::predecessor_account_id();
+        let amount = match self.balances.remove(&account_id) {
+            Some(amount) if amount > 0 => amount,
+            _ => return false,
+        };
+
+        self.refund(&account_id, &receiver_id, amount);
+        true
     }
-    fn bob() -> AccountId {
-        "bob.near".to_string()
+
+    /// Returns the balance of `account_id` left by failed refunds.
+    pub fn get_balance(&self, account_id: AccountId) -> U128 {
+        self.balances.get(&account_id).unwrap_or(0).into()
     }
-    fn carol() -> AccountId {
-        "carol.near".to_string()
+
+    /// Transfers back the token refunds to the caller that couldn't be transferred, to `receiver_id`.
+    /// withdraw_token_balance fails if there is nothing to transfer back.
+    pub fn withdraw_token_balance(&mut self, token_id: AccountId, receiver_id: AccountId) -> bool {
+        let account_id: AccountId = env::predecessor_account_id();
+        let amount = match self.token_balances.remove(&token_balance_key(&token_id, &account_id)) {
+            Some(amount) if amount > 0 => amount,
+            _ => return false,
+        };
+
+        self.transfer_token(&token_id, &receiver_id, amount);
+        true
     }
-    fn auctioned_id() -> AccountId {
-        "auctioned_id1.near".to_string()
+
+    /// Returns the token refunds of `account_id` that couldn't be transferred.
+    pub fn get_token_balance(&self, token_id: AccountId, account_id: AccountId) -> U128 {
+        self.token_balances.get(&token_balance_key(&token_id, &account_id)).unwrap_or(0).into()
     }
 
-    fn get_context(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 2,
-            block_timestamp: 0,
-            account_balance: 3123,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
+    /// Checks the outcome of a token transfer, credits the amount to the token balance of the account if it failed.
+    pub fn on_token_refund(&mut self, token_id: AccountId, account_id: AccountId, amount: U128) -> bool {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Callback can only be called by the registrar");
+
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            return true;
         }
+
+        let key = token_balance_key(&token_id, &account_id);
+        let balance = self.token_balances.get(&key).unwrap_or(0);
+        self.token_balances.insert(&key, &(balance + amount.0));
+        env::log(format!("Failed to refund {} {} to {}, credited to its balance", amount.0, token_id, account_id).as_bytes());
+        false
     }
 
-    fn get_context2(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 1292,
-            block_timestamp: 0,
-            account_balance: 3123,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
+    /// NEP-141 receiver, reveals a sealed bid with the transferred tokens as its masked amount.
+    /// `msg` is a `TokenRevealMsg` in JSON. Returns the amount to give back to `sender_id`, all of it if `msg` is invalid,
+    /// if the auction isn't bid in the token, if the bid was already revealed or if the reveal fails.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> U128 {
+        let token_id: AccountId = env::predecessor_account_id();
+        let reveal: TokenRevealMsg = match near_sdk::serde_json::from_str(&msg) {
+            Ok(reveal) => reveal,
+            Err(_) => return amount,
+        };
+
+        // the currency is the one recorded by the first bid, it is still accepted once the token is delisted
+        let is_bid_in_token = self.auctions
+            .get(&reveal.account_id)
+            .is_some_and(|auction| auction.token_id.as_ref() == Some(&token_id));
+        let bidder_account_id = reveal.bidder_account_id.unwrap_or(sender_id);
+        if !is_bid_in_token || self.is_revealed(&reveal.account_id, &bidder_account_id) {
+            return amount;
+        }
+        if !self.reveal_bid(&reveal.account_id, &bidder_account_id, amount.0, reveal.salt, Some(token_id)) {
+            return amount;
+        }
+        U128(0)
+    }
+
+    /// Accepts `token_id` to reveal bids with `token_config`, or stops accepting it if not set.
+    /// Only the owner can set the tokens. The auctions already bid in a delisted token are still revealed and refunded in it,
+    /// their proceeds stay locked in the registrar.
+    pub fn set_token(&mut self, token_id: AccountId, token_config: Option<TokenConfig>) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+
+        match token_config {
+            Some(token_config) => self.tokens.insert(&token_id, &token_config),
+            None => self.tokens.remove(&token_id),
+        };
+        true
+    }
+
+    /// Returns the configuration of `token_id`, if it is accepted.
+    pub fn get_token(&self, token_id: AccountId) -> Option<TokenConfig> {
+        self.tokens.get(&token_id)
+    }
+
+    /// NEP-171, transfers the token of the name `token_id` to `receiver_id`. Requires exactly one attached yoctoNEAR.
+    /// Panics if the caller doesn't own the token.
+    #[payable]
+    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>) {
+        assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        assert!(approval_id.is_none(), "Approvals are not supported");
+        let sender_id: AccountId = env::predecessor_account_id();
+        self.transfer_name_token(&sender_id, &receiver_id, &token_id, memo);
+    }
+
+    /// NEP-171, transfers the token of the name `token_id` to `receiver_id` and calls `nft_on_transfer` on it.
+    /// The token goes back to the caller if the receiver returns true. Requires exactly one attached yoctoNEAR.
+    #[payable]
+    pub fn nft_transfer_call(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>, msg: String) -> Promise {
+        assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+        assert!(approval_id.is_none(), "Approvals are not supported");
+        let sender_id: AccountId = env::predecessor_account_id();
+        self.transfer_name_token(&sender_id, &receiver_id, &token_id, memo);
+
+        ext_nft_receiver::nft_on_transfer(sender_id.to_string(), sender_id.to_string(), token_id.to_string(), msg, &receiver_id, 0, NFT_ON_TRANSFER_GAS)
+            .then(ext_self::nft_resolve_transfer(sender_id, receiver_id, token_id, &env::current_account_id(), 0, NFT_RESOLVE_TRANSFER_GAS))
+    }
+
+    /// Resolves `nft_transfer_call`, returns true if the receiver kept the token.
+    pub fn nft_resolve_transfer(&mut self, previous_owner_id: AccountId, receiver_id: AccountId, token_id: String) -> bool {
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Callback can only be called by the registrar");
+
+        // the receiver keeps the token unless it returned true
+        let is_returned = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<bool>(&value).unwrap_or(true),
+            _ => true,
+        };
+        if !is_returned {
+            return true;
+        }
+
+        // the receiver already transferred the token
+        if self.name_tokens.get(&token_id).is_none_or(|name_token| name_token.owner_id != receiver_id) {
+            return true;
+        }
+
+        self.transfer_name_token(&receiver_id, &previous_owner_id, &token_id, None);
+        false
+    }
+
+    /// NEP-171, returns the token of the name `token_id`, if it was claimed.
+    pub fn nft_token(&self, token_id: String) -> Option<Token> {
+        self.name_tokens.get(&token_id).map(|name_token| self.token_view(token_id, name_token))
+    }
+
+    /// NEP-181, returns the number of claimed names.
+    pub fn nft_total_supply(&self) -> U128 {
+        U128(self.name_tokens.len().into())
+    }
+
+    /// NEP-181, lists the tokens starting at `from_index`, at most `limit` entries.
+    pub fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let keys = self.name_tokens.keys_as_vector();
+        let values = self.name_tokens.values_as_vector();
+        let from_index = from_index.map_or(0, |from_index| from_index.0 as u64);
+        (from_index..keys.len())
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|index| self.token_view(keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// NEP-181, returns the number of tokens owned by `account_id`.
+    pub fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        U128(self.name_tokens_per_owner.get(&account_id).map_or(0, |token_ids| token_ids.len()).into())
+    }
+
+    /// NEP-181, lists the tokens owned by `account_id` starting at `from_index`, at most `limit` entries.
+    pub fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let token_ids = match self.name_tokens_per_owner.get(&account_id) {
+            Some(token_ids) => token_ids.to_vec(),
+            None => return Vec::new(),
+        };
+        let from_index = from_index.map_or(0, |from_index| from_index.0 as usize);
+        token_ids
+            .into_iter()
+            .skip(from_index)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .filter_map(|token_id| self.nft_token(token_id))
+            .collect()
+    }
+
+    /// NEP-177, returns the metadata of the collection.
+    pub fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: "nft-1.0.0".to_string(),
+            name: "Top level account names".to_string(),
+            symbol: "TLA".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    /// Buys `account_id` in the dutch auction at the current price, creating it with given public key.
+    /// buy fails if dutch auctions are disabled, if someone bid on `account_id` or if the week it was on the market didn't end yet,
+    /// or if the attached deposit is below the current price. The deposit of a failed buy is refunded, the deposit above the price too.
+    #[payable]
+    pub fn buy(&mut self, account_id: AccountId, public_key: Base58PublicKey) -> bool {
+        let amount = env::attached_deposit();
+        let buyer_account_id: AccountId = env::predecessor_account_id();
+
+        // only names without an auction, or with a failed claim, can be bought
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+        auction.reset_if_claim_failed();
+        if auction.start_block_height != 0 {
+            return self.reject_deposit();
+        }
+
+        let price = match self.dutch_price(&account_id) {
+            Some(price) if price > 0 && amount >= price => price,
+            _ => return self.reject_deposit(),
+        };
+        if amount > price {
+            self.refund(&buyer_account_id, &buyer_account_id, amount - price);
+        }
+
+        // record the purchase as a settled auction
+        let block_height = env::block_index();
+        auction.start_block_height = block_height;
+        auction.kind = AuctionKind::Dutch;
+        self.list_auction(&account_id, &mut auction);
+        auction.bids.insert(&buyer_account_id, &Bid { amount: price, commitment: Vec::new(), block_height, beneficiary_account_id: None });
+        auction.reveals.insert(&buyer_account_id, &price);
+        auction.standings.record(&buyer_account_id, price, block_height);
+
+        // TODO: burn the price
+        env::log(format!("{} bought {} for {}", buyer_account_id, account_id, price).as_bytes());
+
+        self.create_account(&mut auction, &account_id, &buyer_account_id, AccountSetup::full_access(public_key));
+        self.auctions.insert(&account_id, &auction);
+        self.add_participation(&buyer_account_id, &account_id);
+        true
+    }
+
+    /// Enables the dutch auctions with `dutch_auction` as their schedule, or disables them if not set.
+    /// Only the owner can set the schedule.
+    pub fn set_dutch_auction(&mut self, dutch_auction: Option<DutchAuctionConfig>) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+        if let Some(config) = &dutch_auction {
+            if config.start_price.0 < config.end_price.0 {
+                return false;
+            }
+        }
+
+        self.dutch_auction = dutch_auction;
+        true
+    }
+
+    /// Returns the current price of `account_id` in the dutch auction, if it can be bought.
+    pub fn get_dutch_price(&self, account_id: AccountId) -> Option<U128> {
+        if self.auctions.get(&account_id).is_some_and(|auction| auction.claim_status != ClaimStatus::Failed) {
+            return None;
+        }
+        self.dutch_price(&account_id).map(|price| price.into())
+    }
+
+    /// Registers `account_id` at the price of its length tier, creating it with given public key.
+    /// register fails if no tier applies to the length of `account_id`, if it is auctioned or due for auction,
+    /// or if the attached deposit is below the price. The deposit of a failed register is refunded, the deposit above the price too.
+    #[payable]
+    pub fn register(&mut self, account_id: AccountId, public_key: Base58PublicKey) -> bool {
+        let amount = env::attached_deposit();
+        let buyer_account_id: AccountId = env::predecessor_account_id();
+
+        // only names without an auction, or with a failed claim, can be registered
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+        auction.reset_if_claim_failed();
+        if auction.start_block_height != 0 || self.is_due_for_auction(&account_id) {
+            return self.reject_deposit();
+        }
+
+        let price = match self.register_price(&account_id) {
+            Some(price) if amount >= price => price,
+            _ => return self.reject_deposit(),
+        };
+        if amount > price {
+            self.refund(&buyer_account_id, &buyer_account_id, amount - price);
+        }
+
+        // record the registration as a settled auction
+        let block_height = env::block_index();
+        auction.start_block_height = block_height;
+        auction.kind = AuctionKind::Fixed;
+        self.list_auction(&account_id, &mut auction);
+        auction.bids.insert(&buyer_account_id, &Bid { amount: price, commitment: Vec::new(), block_height, beneficiary_account_id: None });
+        auction.reveals.insert(&buyer_account_id, &price);
+        auction.standings.record(&buyer_account_id, price, block_height);
+
+        // TODO: burn the price
+        env::log(format!("{} registered {} for {}", buyer_account_id, account_id, price).as_bytes());
+
+        self.create_account(&mut auction, &account_id, &buyer_account_id, AccountSetup::full_access(public_key));
+        self.auctions.insert(&account_id, &auction);
+        self.add_participation(&buyer_account_id, &account_id);
+        true
+    }
+
+    /// Sets the prices of the direct registration per name length tier, an empty list disables the registration.
+    /// Only the owner can set the prices.
+    pub fn set_register_prices(&mut self, register_prices: Vec<PriceTier>) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+
+        self.register_prices = register_prices;
+        true
+    }
+
+    /// Returns the price of the direct registration of `account_id`, if it can be registered.
+    pub fn get_register_price(&self, account_id: AccountId) -> Option<U128> {
+        if self.auctions.get(&account_id).is_some_and(|auction| auction.claim_status != ClaimStatus::Failed)
+            || self.is_due_for_auction(&account_id) {
+            return None;
+        }
+        self.register_price(&account_id).map(|price| price.into())
+    }
+
+    /// Reserves `account_id`, assigning it to `assigned_account_id` if set, or changes the account it is assigned to.
+    /// Only the owner can reserve names. Reserve fails if `account_id` isn't a valid name, or is auctioned, bought or being claimed.
+    pub fn reserve_name(&mut self, account_id: AccountId, assigned_account_id: Option<AccountId>) -> bool {
+        if env::predecessor_account_id() != self.owner_id || !self.is_valid_name(&account_id) {
+            return false;
+        }
+        if self.auctions.get(&account_id).is_some_and(|auction| auction.claim_status != ClaimStatus::Failed) {
+            return false;
+        }
+
+        self.reserved_names.insert(&account_id, &assigned_account_id);
+        true
+    }
+
+    /// Releases the reserved `account_id`. Only the owner can release names.
+    pub fn unreserve_name(&mut self, account_id: AccountId) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+
+        self.reserved_names.remove(&account_id).is_some()
+    }
+
+    /// Returns true if `account_id` is reserved.
+    pub fn is_reserved(&self, account_id: AccountId) -> bool {
+        self.reserved_names.get(&account_id).is_some()
+    }
+
+    /// Returns the account the reserved `account_id` is assigned to, if any.
+    pub fn get_reserved_for(&self, account_id: AccountId) -> Option<AccountId> {
+        self.reserved_names.get(&account_id).flatten()
+    }
+
+    /// Publishes the Merkle root of the pre-allocated names, or disables the pre-allocation if not set.
+    /// Only the owner can set the root.
+    pub fn set_allocation_root(&mut self, allocation_root: Option<Base64VecU8>) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+
+        self.allocation_root = allocation_root.map(|root| root.0);
+        true
+    }
+
+    /// Returns the Merkle root of the pre-allocated names, if set.
+    pub fn get_allocation_root(&self) -> Option<Base64VecU8> {
+        self.allocation_root.clone().map(Base64VecU8)
+    }
+
+    /// Claims the pre-allocated `account_id` for the caller, creating it with given public key.
+    /// `proof` are the siblings of `hash(account_id:caller)` up to the published root.
+    /// claim_allocated fails if the proof doesn't lead to the root or if the name was already auctioned, claimed or is being claimed.
+    /// The outcome is checked by `on_claim`.
+    pub fn claim_allocated(&mut self, account_id: AccountId, public_key: Base58PublicKey, proof: Vec<Base64VecU8>) -> bool {
+        let claimer_account_id: AccountId = env::predecessor_account_id();
+        let root = match &self.allocation_root {
+            Some(root) if self.is_valid_name(&account_id) => root,
+            _ => return false,
+        };
+        let proof: Vec<Vec<u8>> = proof.into_iter().map(|node| node.0).collect();
+        if !verify_merkle_proof(allocation_leaf(&account_id, &claimer_account_id), &proof, root) {
+            return false;
+        }
+
+        self.create_assigned(&account_id, &claimer_account_id, AccountSetup::full_access(public_key), 0)
+    }
+
+    /// Assigns the right to claim `account_id` to `assignee_account_id`, which then claims it with its own key.
+    /// Only the assignee can claim the name, the winning bidder can still change the assignee until it is claimed.
+    /// The winning bidder keeps the refunds of the auction, the assignee funds the initial balance of the account.
+    /// Assign fails if the win doesn't belong to the caller or if the name was already claimed or is being claimed.
+    pub fn assign_claim(&mut self, account_id: AccountId, assignee_account_id: AccountId) -> bool {
+        let winning_account_id: AccountId = env::predecessor_account_id();
+        let mut auction = match self.auctions.get(&account_id) {
+            Some(auction) => auction,
+            None => return false,
+        };
+        let is_closed = self.close_candle(&mut auction);
+        if auction.claim_status != ClaimStatus::Unclaimed || !self.is_settled(&auction) || auction.winning_beneficiary() != Some(winning_account_id.clone()) {
+            if is_closed {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+            }
+            return false;
+        }
+
+        env::log(format!("{} assigned the claim of {} to {}", winning_account_id, account_id, assignee_account_id).as_bytes());
+        auction.claim_assignee = Some(assignee_account_id);
+        self.auctions.insert(&account_id, &auction);
+        true
+    }
+
+    /// Closes the candle auction for `account_id` at a block picked at random within its bidding period.
+    /// Close fails if the auction isn't a candle auction, is still in the bidding period or is already closed.
+    pub fn close_auction(&mut self, account_id: AccountId) -> bool {
+        let mut auction = match self.auctions.get(&account_id) {
+            Some(auction) => auction,
+            None => return false,
+        };
+        if !self.close_candle(&mut auction) {
+            return false;
+        }
+
+        self.auctions.insert(&account_id, &auction);
+        true
+    }
+
+    /// Sets the format of the auction for `account_id`, or resets it to the default format if `auction_kind` is not set.
+    /// Only the owner can set the format, auctions that already started keep theirs. Dutch auctions can't be set per name.
+    pub fn set_auction_kind(&mut self, account_id: AccountId, auction_kind: Option<AuctionKind>) -> bool {
+        if env::predecessor_account_id() != self.owner_id {
+            return false;
+        }
+
+        match auction_kind {
+            Some(AuctionKind::Dutch) | Some(AuctionKind::Fixed) | Some(AuctionKind::Reserved) => return false,
+            Some(auction_kind) => self.auction_kinds.insert(&account_id, &auction_kind),
+            None => self.auction_kinds.remove(&account_id),
+        };
+        true
+    }
+
+    /// Returns the parent account of the names in the sub-account mode, if set.
+    pub fn get_suffix(&self) -> Option<AccountId> {
+        self.suffix.clone()
+    }
+
+    /// Returns the format `account_id` is auctioned in.
+    pub fn get_auction_kind(&self, account_id: AccountId) -> AuctionKind {
+        self.auction_kinds.get(&account_id).unwrap_or(self.auction_kind)
+    }
+
+    /// Lists the auctions starting at `from_index`, at most `limit` entries.
+    /// If `phase` is set, only the auctions currently in that phase are returned, ordered by start block height,
+    /// and `from_index` counts the auctions in that phase.
+    pub fn list_auctions(&self, from_index: u64, limit: u64, phase: Option<AuctionPhase>) -> Vec<AuctionView> {
+        let phase = match phase {
+            Some(phase) => phase,
+            None => {
+                let keys = self.auctions.keys_as_vector();
+                let values = self.auctions.values_as_vector();
+                return (from_index..std::cmp::min(keys.len(), from_index.saturating_add(limit)))
+                    .map(|index| self.auction_view(keys.get(index).unwrap(), values.get(index).unwrap()))
+                    .collect();
+            }
+        };
+
+        // the phase of an auction only depends on its start block height, names listed under 0 are closed
+        let current_blockheight = env::block_index();
+        let bidding_start = current_blockheight.saturating_sub(self.auction_period) + 1;
+        let reveal_start = current_blockheight.saturating_sub(self.auction_period + self.reveal_period) + 1;
+        let (start, end) = match phase {
+            AuctionPhase::Bidding => (Bound::Included(bidding_start), Bound::Unbounded),
+            AuctionPhase::Reveal => (Bound::Included(reveal_start), Bound::Excluded(bidding_start)),
+            AuctionPhase::Closed => (Bound::Included(0), Bound::Excluded(reveal_start)),
+        };
+        let start = start.map(|block_height| (block_height, String::new()));
+        let end = end.map(|block_height| (block_height, String::new()));
+        self.auctions_by_start
+            .range((start, end))
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|((_, account_id), _)| {
+                let auction = self.auctions.get(&account_id).unwrap();
+                self.auction_view(account_id, auction)
+            })
+            .collect()
+    }
+
+    /// Lists the bids for `account_id` starting at `from_index`, at most `limit` entries.
+    /// Commitments and amounts of sealed bids are only returned once the bidding phase is over.
+    pub fn list_bids(&self, account_id: AccountId, from_index: u64, limit: u64) -> Vec<BidView> {
+        let auction = match self.auctions.get(&account_id) {
+            Some(auction) => auction,
+            None => return vec![],
+        };
+        let is_revealed = self.auction_phase(&auction) != AuctionPhase::Bidding || auction.kind == AuctionKind::Open;
+        let keys = auction.bids.keys_as_vector();
+        let values = auction.bids.values_as_vector();
+        (from_index..std::cmp::min(keys.len(), from_index.saturating_add(limit)))
+            .map(|index| {
+                let bid = values.get(index).unwrap();
+                BidView {
+                    bidder_account_id: keys.get(index).unwrap(),
+                    commitment: if is_revealed && !bid.commitment.is_empty() { Some(String::from_utf8_lossy(&bid.commitment).into_owned()) } else { None },
+                    amount: if is_revealed { Some(bid.amount.into()) } else { None },
+                }
+            })
+            .collect()
+    }
+
+    /// Lists the auctions `account_id` bid on, with what is left to do for each of them.
+    pub fn get_account_activity(&self, account_id: AccountId) -> Vec<ParticipationView> {
+        let auction_ids = match self.participations.get(&account_id) {
+            Some(auction_ids) => auction_ids,
+            None => return vec![],
+        };
+        auction_ids
+            .iter()
+            .filter_map(|auction_id| {
+                let auction = self.auctions.get(&auction_id)?;
+                let bid = auction.bids.get(&account_id)?;
+                let status = self.participation_status(&auction, &account_id, &bid);
+                Some(ParticipationView { account_id: auction_id, status })
+            })
+            .collect()
+    }
+}
+
+impl Registrar {
+    /// Reveals the bid of `revealer_account_id` on `account_id`, `masked_amount` was deposited in `token_id`, NEAR if not set.
+    fn reveal_bid(&mut self, account_id: &AccountId, revealer_account_id: &AccountId, masked_amount: Balance, salt: String, token_id: Option<AccountId>) -> bool {
+        // get the auction that match the account id, from the map
+        let mut auction = self.auctions.get(account_id).unwrap_or_else(|| Auction::new(account_id, 0));
+
+        // insert into reaveals map if it matches the commitment
+        if auction.start_block_height != 0 {
+            // check if auction is in progress or if reveal period expired
+            let current_blockheight = env::block_index();
+            if (current_blockheight - auction.start_block_height < self.auction_period) || 
+               (current_blockheight - auction.start_block_height >= self.auction_period + self.reveal_period) {
+                   // restore the in-memory copy
+                   self.auctions.insert(account_id, &auction);              
+                   return false;
+            }
+
+            // check if the name was already claimed or is being claimed
+            if auction.claim_status != ClaimStatus::Unclaimed {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // check if the bid was already revealed
+            if auction.reveals.get(revealer_account_id).is_some() {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // check if `hash(masked_amount + salt)` != `commitment` by env::predeccessor_account_id()`
+            // if bidder already exists return false
+            let amount = 0;
+            let commitment: Vec<u8> = Vec::new();
+            let empty_bid: Bid = Bid { amount, commitment, block_height: 0, beneficiary_account_id: None };
+            let mut bid = auction.bids.get(revealer_account_id).unwrap_or(empty_bid);
+            if !bid.commitment.is_empty() {
+                // calculate hash(masked_amount + salt)
+                let commitment_hash = masked_amount.to_string() + &salt;
+                let revealer_commitment = &bs58::encode(&commitment_hash).into_string();
+                if str::from_utf8(&bid.commitment).unwrap() != revealer_commitment {
+                    // restore the in-memory copy
+                    auction.bids.insert(revealer_account_id, &bid);
+                    self.auctions.insert(account_id, &auction);
+                    return false;
+                }
+
+                // the bid is revealed in the currency of the auction, token bids have to meet the reserve price
+                if auction.token_id != token_id || masked_amount < self.reserve_price(&token_id) {
+                    // restore the in-memory copy
+                    auction.bids.insert(revealer_account_id, &bid);
+                    self.auctions.insert(account_id, &auction);
+                    return false;
+                }
+
+                // set the missing bid amount info
+                bid.amount = masked_amount;
+
+                // restore the in-memory bid copy
+                auction.bids.insert(revealer_account_id, &bid);
+            } else {
+                // restore the in-memory bid copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+            
+            // insert into reveal's map
+            auction.reveals.insert(revealer_account_id, &masked_amount);
+            auction.standings.record(revealer_account_id, masked_amount, bid.block_height);
+
+            // restore the in-memory copy
+            self.auctions.insert(account_id, &auction);
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    /// Returns true if the sealed bid of `bidder_account_id` on `account_id` was already revealed.
+    fn is_revealed(&self, account_id: &AccountId, bidder_account_id: &AccountId) -> bool {
+        self.auctions
+            .get(account_id)
+            .is_some_and(|auction| auction.kind == AuctionKind::Sealed && auction.reveals.get(bidder_account_id).is_some())
+    }
+
+    /// Returns the minimum amount a bid in `token_id` has to reveal, 0 for NEAR.
+    fn reserve_price(&self, token_id: &Option<AccountId>) -> Balance {
+        token_id
+            .as_ref()
+            .and_then(|token_id| self.tokens.get(token_id))
+            .map_or(0, |config| config.reserve_price.0)
+    }
+
+    /// Refunds `amount` of a bid to `account_id` in the currency of `auction`.
+    fn refund_bid(&self, auction: &Auction, account_id: &AccountId, amount: Balance) {
+        match &auction.token_id {
+            Some(token_id) => self.transfer_token(token_id, account_id, amount),
+            None => self.refund(account_id, account_id, amount),
+        }
+    }
+
+    /// Transfers `amount` of `token_id` to `account_id`, then checks if the transfer succeeded.
+    /// `ft_transfer` requires one attached yoctoNEAR, paid by the registrar.
+    fn transfer_token(&self, token_id: &AccountId, account_id: &AccountId, amount: Balance) {
+        ext_ft::ft_transfer(account_id.to_string(), amount.into(), None, token_id, 1, FT_TRANSFER_GAS).then(ext_self::on_token_refund(
+            token_id.to_string(),
+            account_id.to_string(),
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            ON_REFUND_GAS,
+        ));
+    }
+
+    /// Returns true if `account_id` is on the market based on `hash(account_id) % 52 == weeks from start_block_height`.
+    /// Reserved names, names that aren't valid and names long enough to be registered directly are never on the market.
+    fn is_open_for_auction(&self, account_id: &AccountId) -> bool {
+        self.is_open_for_auction_at(account_id, env::block_index())
+    }
+
+    /// Returns true if `account_id` is auctioned in the week of `block_height`, see `is_open_for_auction`.
+    fn is_open_for_auction_at(&self, account_id: &AccountId, block_height: BlockHeight) -> bool {
+        if !self.is_valid_name(account_id) || self.reserved_names.get(account_id).is_some() {
+            return false;
+        }
+        if self.name_length(account_id) >= MIN_ALLOWED_TOP_LEVEL_ACCOUNT_LENGTH {
+            return false;
+        }
+
+        // calculate number of weeks until the auction started
+        let weeks = (block_height - self.start_block_height) / self.auction_period;
+
+        weeks == auction_week(account_id)
+    }
+
+    /// Claims `account_id` for the caller, creating it with `setup`, see `claim`.
+    /// `deposit` is attached to the claim and funds the initial balance, it is refunded by the caller on failure.
+    fn claim_account(&mut self, account_id: &AccountId, setup: AccountSetup, deposit: Balance) -> bool {
+        // reserved names are claimed by the account they are assigned to
+        if self.reserved_names.get(account_id).is_some() {
+            return self.claim_reserved(account_id, setup, deposit);
+        }
+
+        // get the auction that match the account id, from the map
+        let mut auction = self.auctions.get(account_id).unwrap_or_else(|| Auction::new(account_id, 0));
+
+        // withdraw funds for loosing bider
+        if auction.start_block_height != 0 {
+            // check if auction is in progress
+            let current_blockheight = env::block_index();
+            if current_blockheight - auction.start_block_height < self.auction_period {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // check if reaveal is in progress and if all bidders revealed themselves
+            if current_blockheight - auction.start_block_height < self.auction_period + self.reveal_period
+                && !self.is_fully_revealed(&auction) {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // check if the name was already claimed
+            if auction.claim_status != ClaimStatus::Unclaimed {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // get the winner and the second highest bid, if all the bids are 0 return false
+            self.close_candle(&mut auction);
+            let settlement = match auction.settlement() {
+                Some(settlement) => settlement,
+                None => {
+                    // restore the in-memory copy
+                    self.auctions.insert(account_id, &auction);
+                    return false;
+                }
+            };
+            let winning_account_id = settlement.winning_account_id;
+
+            // check if the claimer is the account the claim was assigned to, or the beneficiary of the winning bid if none
+            let claimer_account_id: AccountId = env::predecessor_account_id();
+            let expected_claimer_account_id = auction.claim_assignee.clone().unwrap_or_else(|| auction.beneficiary(&winning_account_id));
+            if expected_claimer_account_id != claimer_account_id {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // the surplus is paid out of the stored winning bid, which no longer covers the price if it was paid out already
+            let winning_amount = auction.bids.get(&winning_account_id).map_or(0, |bid| bid.amount);
+            if winning_amount < settlement.price {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // the initial balance is paid out of the deposit, and out of the bid the winner doesn't pay if the bidder claims itself
+            let surplus = winning_amount - settlement.price;
+            let funding_surplus = if winning_account_id == claimer_account_id && auction.token_id.is_none() { surplus } else { 0 };
+            let initial_balance = setup.initial_balance.map_or(0, |initial_balance| initial_balance.0);
+            if initial_balance > funding_surplus + deposit {
+                // restore the in-memory copy
+                self.auctions.insert(account_id, &auction);
+                return false;
+            }
+
+            // the winner pays the second highest bid, transfer back the rest
+            if funding_surplus + deposit > initial_balance {
+                self.refund(&claimer_account_id, &claimer_account_id, funding_surplus + deposit - initial_balance);
+            }
+            if surplus > funding_surplus {
+                self.refund_bid(&auction, &winning_account_id, surplus);
+            }
+            if surplus > 0 {
+                if let Some(mut bid) = auction.bids.get(&winning_account_id) {
+                    bid.amount = settlement.price;
+                    auction.bids.insert(&winning_account_id, &bid);
+                }
+            }
+
+            // TODO: burn the second_highest_bid
+            env::log(format!("{} won {} for {}", winning_account_id, account_id, settlement.price).as_bytes());
+
+            // creates the new name with given public key for the winer
+            self.create_account(&mut auction, account_id, &winning_account_id, setup);
+            self.remove_expired_blind_bids(EXPIRED_BLIND_BIDS_LIMIT);
+            
+            // get the vector of bidder_account_id
+            let bids = auction.bids.iter();
+            let mut bidders: Vec<AccountId> = Vec::new();
+            for (bidder_account_id, _bid) in bids {
+                if winning_account_id != bidder_account_id {
+                    bidders.push(bidder_account_id);
+                }
+            }
+
+            // withdraw all other bids automatically
+            for bidder_account_id in bidders {
+                let amount = 0;
+                let commitment: Vec<u8> = Vec::new();
+                let empty_bid: Bid = Bid { amount, commitment, block_height: 0, beneficiary_account_id: None };
+                let mut bid = auction.bids.get(&bidder_account_id).unwrap_or(empty_bid); 
+                if !bid.commitment.is_empty() || auction.kind == AuctionKind::Candle {
+                    // transfer back the bid.amount
+                    if bid.amount > 0 {
+                        self.refund_bid(&auction, &bidder_account_id, bid.amount);
+                        bid.amount = 0;    
+                    }
+
+                    // restore the in-memory bid copy
+                    auction.bids.insert(&bidder_account_id, &bid);
+                }
+            }
+
+            // restore the in-memory copy
+            self.auctions.insert(account_id, &auction);
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    /// Creates the reserved `account_id` with `setup` for the account it is assigned to, the outcome is checked by `on_claim`.
+    /// The claim fails if the caller isn't the assigned account or if the name was already claimed or is being claimed.
+    fn claim_reserved(&mut self, account_id: &AccountId, setup: AccountSetup, deposit: Balance) -> bool {
+        let claimer_account_id: AccountId = env::predecessor_account_id();
+        if self.reserved_names.get(account_id).flatten() != Some(claimer_account_id.clone()) {
+            return false;
+        }
+
+        self.create_assigned(account_id, &claimer_account_id, setup, deposit)
+    }
+
+    /// Creates `account_id` with `setup` for the account it is assigned to, recording it as a settled auction without bids.
+    /// The initial balance is paid out of `deposit`, the rest is refunded.
+    /// Fails if `account_id` was already auctioned, claimed or is being claimed, or if the initial balance is above `deposit`.
+    fn create_assigned(&mut self, account_id: &AccountId, claimer_account_id: &AccountId, setup: AccountSetup, deposit: Balance) -> bool {
+        let mut auction = self.auctions.get(account_id).unwrap_or_else(|| Auction::new(account_id, 0));
+        auction.reset_if_claim_failed();
+        let initial_balance = setup.initial_balance.map_or(0, |initial_balance| initial_balance.0);
+        if auction.start_block_height != 0 || initial_balance > deposit {
+            return false;
+        }
+        if deposit > initial_balance {
+            self.refund(claimer_account_id, claimer_account_id, deposit - initial_balance);
+        }
+
+        // record the claim as a settled auction without bids
+        auction.start_block_height = env::block_index();
+        auction.kind = AuctionKind::Reserved;
+        self.list_auction(account_id, &mut auction);
+        env::log(format!("{} claimed reserved {}", claimer_account_id, account_id).as_bytes());
+
+        self.create_account(&mut auction, account_id, claimer_account_id, setup);
+        self.auctions.insert(account_id, &auction);
+        true
+    }
+
+    /// Returns true if `account_id` can be auctioned, any name in the top level mode,
+    /// a valid `label.suffix` account id with a label without dots in the sub-account mode.
+    fn is_valid_name(&self, account_id: &AccountId) -> bool {
+        match &self.suffix {
+            Some(suffix) => account_id
+                .strip_suffix(suffix.as_str())
+                .and_then(|label| label.strip_suffix('.'))
+                .is_some_and(|label| !label.is_empty() && !label.contains('.') && env::is_valid_account_id(account_id.as_bytes())),
+            None => true,
+        }
+    }
+
+    /// Returns the length of `account_id` without the suffix.
+    fn name_length(&self, account_id: &AccountId) -> usize {
+        match &self.suffix {
+            Some(suffix) => account_id.len().saturating_sub(suffix.len() + 1),
+            None => account_id.len(),
+        }
+    }
+
+    /// Returns true if `account_id` is short enough to be auctioned and the week it is on the market didn't end yet.
+    fn is_due_for_auction(&self, account_id: &AccountId) -> bool {
+        let weeks = (env::block_index() - self.start_block_height) / self.auction_period;
+        self.name_length(account_id) < MIN_ALLOWED_TOP_LEVEL_ACCOUNT_LENGTH && weeks <= auction_week(account_id)
+    }
+
+    /// Returns the price of the direct registration of `account_id`, from the longest tier it is long enough for.
+    /// Returns `None` if `account_id` isn't a valid name or is reserved.
+    fn register_price(&self, account_id: &AccountId) -> Option<Balance> {
+        if !self.is_valid_name(account_id) || self.reserved_names.get(account_id).is_some() {
+            return None;
+        }
+        let name_length = self.name_length(account_id) as u64;
+        self.register_prices
+            .iter()
+            .filter(|tier| name_length >= tier.min_length)
+            .max_by_key(|tier| tier.min_length)
+            .map(|tier| tier.price.0)
+    }
+
+    /// Returns the price of `account_id` in the dutch auction at the current block height.
+    /// Returns `None` if dutch auctions are disabled, if `account_id` isn't a valid name or is reserved,
+    /// or if the week it was on the market didn't end yet.
+    fn dutch_price(&self, account_id: &AccountId) -> Option<Balance> {
+        if !self.is_valid_name(account_id) || self.reserved_names.get(account_id).is_some() {
+            return None;
+        }
+        let config = self.dutch_auction.as_ref()?;
+        let week_end_block_height = self.start_block_height + (auction_week(account_id) + 1) * self.auction_period;
+        let elapsed = env::block_index().checked_sub(week_end_block_height)?;
+        if elapsed >= config.duration {
+            return Some(config.end_price.0);
+        }
+
+        let price_drop = (config.start_price.0 - config.end_price.0) * Balance::from(elapsed) / Balance::from(config.duration);
+        Some(config.start_price.0 - price_drop)
+    }
+
+    /// Creates `account_id` with the keys, the contract and the initial balance of `setup` for the winner of `auction`,
+    /// the outcome is checked by `on_claim`.
+    fn create_account(&self, auction: &mut Auction, account_id: &AccountId, winning_account_id: &AccountId, setup: AccountSetup) {
+        auction.claim_status = ClaimStatus::Pending;
+        auction.initial_balance = setup.initial_balance.map_or(0, |initial_balance| initial_balance.0);
+        let mut promise = Promise::new(account_id.to_string()).create_account();
+        if auction.initial_balance > 0 {
+            promise = promise.transfer(auction.initial_balance);
+        }
+        for key in setup.keys {
+            promise = match key.permission {
+                // an allowance of 0 is unlimited, set allowances are above 0
+                Some(permission) => promise.add_access_key(
+                    key.public_key.0,
+                    permission.allowance.map_or(0, |allowance| allowance.0),
+                    permission.receiver_id,
+                    permission.method_names.join(",").into_bytes(),
+                ),
+                None => promise.add_full_access_key(key.public_key.0),
+            };
+        }
+        if let Some(contract) = setup.contract {
+            promise = promise.deploy_contract(contract.code.0);
+            if let Some(method_name) = contract.init_method_name {
+                let init_args = contract.init_args.map_or(Vec::new(), |init_args| init_args.0);
+                promise = promise.function_call(method_name.into_bytes(), init_args, 0, INIT_GAS);
+            }
+        }
+        promise.then(ext_self::on_claim(
+            account_id.to_string(),
+            winning_account_id.to_string(),
+            &env::current_account_id(),
+            0,
+            ON_CLAIM_GAS,
+        ));
+    }
+
+    /// Records the sealed bid of `bidder_account_id` on `account_id`, starting the auction if needed.
+    fn place_bid(&mut self, bidder_account_id: &AccountId, account_id: AccountId, commitment: Vec<u8>, beneficiary_account_id: Option<AccountId>, token_id: Option<AccountId>) -> bool {
+        assert!(!commitment.is_empty(), "Commitment should not be empty");
+        let new_bid = Bid {
+            amount: 0,
+            commitment,
+            block_height: env::block_index(),
+            beneficiary_account_id,
+        };
+
+        // get the auction that match the account id, from the map
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+
+        // if the account couldn't be created for the winner, the name is auctioned again
+        auction.reset_if_claim_failed();
+
+        // if there is an auction, insert the new bid to map
+        if auction.start_block_height != 0 {
+            // check if auction expired, isn't a sealed auction or takes bids in another currency
+            let current_blockheight = env::block_index();
+            if current_blockheight - auction.start_block_height >= self.auction_period || auction.kind != AuctionKind::Sealed || auction.token_id != token_id {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+                return false;
+            }
+
+            // if bidder already exists return false
+            let amount = 0;
+            let commitment: Vec<u8> = Vec::new();
+            let empty_bid: Bid = Bid { amount, commitment, block_height: 0, beneficiary_account_id: None };
+            let bid = auction.bids.get(bidder_account_id).unwrap_or(empty_bid); 
+            if !bid.commitment.is_empty() {
+                // restore the in-memory copy
+                self.auctions.insert(&account_id, &auction);
+                return false;
+            }
+   
+            // insert into bids map
+            auction.bids.insert(bidder_account_id, &new_bid);
+
+            // restore the in-memory copy
+            self.auctions.insert(&account_id, &auction);
+            self.add_participation(bidder_account_id, &account_id);
+        } else {      
+            // check if account_id is open for a sealed auction
+            if !self.is_open_for_auction(&account_id) || self.get_auction_kind(account_id.to_string()) != AuctionKind::Sealed {
+                return false;
+            }
+
+            // insert this new auction to auction list, in the currency of the first bid
+            auction.start_block_height = env::block_index();
+            auction.kind = AuctionKind::Sealed;
+            auction.token_id = token_id;
+            auction.bids.insert(bidder_account_id, &new_bid);
+            self.list_auction(&account_id, &mut auction);
+            self.auctions.insert(&account_id, &auction);       
+            self.add_participation(bidder_account_id, &account_id);
+        }
+
+        true
+    }
+
+    /// Matches the blind bid of `bidder_account_id` to the auction of `account_id` and reveals it, see `reveal_blind`.
+    /// The caller pays the masked amount and refunds it on failure. A blind bid that turns out ineligible is dropped.
+    fn disclose_blind_bid(&mut self, bidder_account_id: &AccountId, account_id: AccountId, masked_amount: Balance, salt: String) -> bool {
+        let commitment = bs58::encode(env::sha256(format!("{}:{}:{}", account_id, masked_amount, salt).as_bytes())).into_string();
+        let key = blind_bid_key(bidder_account_id, commitment.as_bytes());
+        let blind_bid = match self.blind_bids.get(&key) {
+            Some(blind_bid) => blind_bid,
+            None => return false,
+        };
+        let block_height = blind_bid.block_height;
+
+        // match the bid to the auction of the name, or start it at the block of the blind bid
+        let mut auction = self.auctions.get(&account_id).unwrap_or_else(|| Auction::new(&account_id, 0));
+        auction.reset_if_claim_failed();
+        if auction.claim_status != ClaimStatus::Unclaimed {
+            return false;
+        }
+        if auction.start_block_height == 0 {
+            auction.start_block_height = block_height;
+            auction.kind = self.get_auction_kind(account_id.to_string());
+        }
+        let is_eligible = auction.kind == AuctionKind::Sealed
+            && auction.token_id.is_none()
+            && self.is_open_for_auction_at(&account_id, block_height)
+            && block_height < auction.start_block_height + self.auction_period
+            && auction.bids.get(bidder_account_id).is_none();
+        match self.auction_phase(&auction) {
+            AuctionPhase::Bidding if is_eligible => return false,
+            AuctionPhase::Reveal if is_eligible => {}
+            _ => {
+                self.remove_blind_bid(&key, &blind_bid);
+                return false;
+            }
+        }
+
+        // record the disclosed bid with the commitment of a sealed bid, then reveal it
+        let commitment = bs58::encode(masked_amount.to_string() + &salt).into_string().into_bytes();
+        auction.bids.insert(bidder_account_id, &Bid { amount: 0, commitment, block_height, beneficiary_account_id: None });
+        self.list_auction(&account_id, &mut auction);
+        self.auctions.insert(&account_id, &auction);
+        self.add_participation(bidder_account_id, &account_id);
+        self.remove_blind_bid(&key, &blind_bid);
+        self.reveal_bid(&account_id, bidder_account_id, masked_amount, salt, None)
+    }
+
+    /// Draws `amount` from the escrow balance of `account_id`, returns false if the balance is below `amount`.
+    fn draw_escrow(&mut self, account_id: &AccountId, amount: Balance) -> bool {
+        let balance = self.escrow_balances.get(account_id).unwrap_or(0);
+        if balance < amount {
+            return false;
+        }
+
+        if balance == amount {
+            self.escrow_balances.remove(account_id);
+        } else {
+            self.escrow_balances.insert(account_id, &(balance - amount));
+        }
+        true
+    }
+
+    /// Credits `amount` back to the escrow balance of `account_id`.
+    fn credit_escrow(&mut self, account_id: &AccountId, amount: Balance) {
+        let balance = self.escrow_balances.get(account_id).unwrap_or(0);
+        self.escrow_balances.insert(account_id, &(balance + amount));
+    }
+
+    /// Refunds the attached deposit of a call that failed, returns false.
+    fn reject_deposit(&self) -> bool {
+        let account_id: AccountId = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        if amount > 0 {
+            self.refund(&account_id, &account_id, amount);
+        }
+        false
+    }
+
+    /// Transfers `amount` to `receiver_id`, crediting it back to the balance of `account_id` if the transfer fails.
+    fn refund(&self, account_id: &AccountId, receiver_id: &AccountId, amount: Balance) {
+        Promise::new(receiver_id.to_string())
+            .transfer(amount)
+            .then(ext_self::on_refund(
+                account_id.to_string(),
+                amount.into(),
+                &env::current_account_id(),
+                0,
+                ON_REFUND_GAS,
+            ));
+    }
+
+    /// Returns the view of the auction of `account_id`, the winner and price are set once the auction is settled.
+    fn auction_view(&self, account_id: AccountId, auction: Auction) -> AuctionView {
+        let settlement = if self.is_settled(&auction) { auction.settlement() } else { None };
+        AuctionView {
+            account_id,
+            start_block_height: auction.start_block_height,
+            kind: auction.kind,
+            phase: self.auction_phase(&auction),
+            num_bids: auction.bids.len(),
+            num_reveals: auction.reveals.len(),
+            claim_status: auction.claim_status,
+            close_block_height: auction.close_block_height,
+            winning_account_id: settlement.as_ref().map(|settlement| auction.beneficiary(&settlement.winning_account_id)),
+            price: settlement.map(|settlement| settlement.price.into()),
+            claim_assignee: auction.claim_assignee,
+            token_id: auction.token_id,
+        }
+    }
+
+    /// Lists `account_id` in `auctions_by_start` under the start block height of its auction, moving it if the auction was restarted.
+    /// Dutch auctions, registrations and reserved names are listed under 0 since they are always closed.
+    fn list_auction(&mut self, account_id: &AccountId, auction: &mut Auction) {
+        let block_height = match auction.kind {
+            AuctionKind::Dutch | AuctionKind::Fixed | AuctionKind::Reserved => 0,
+            _ => auction.start_block_height,
+        };
+        if auction.listed_block_height == Some(block_height) {
+            return;
+        }
+        if let Some(listed_block_height) = auction.listed_block_height {
+            self.auctions_by_start.remove(&(listed_block_height, account_id.clone()));
+        }
+        self.auctions_by_start.insert(&(block_height, account_id.clone()), &());
+        auction.listed_block_height = Some(block_height);
+    }
+
+    /// Returns the phase `auction` is in at the current block height.
+    fn auction_phase(&self, auction: &Auction) -> AuctionPhase {
+        // dutch auctions, registrations and reserved names are settled as soon as the name is bought or claimed
+        if auction.kind == AuctionKind::Dutch || auction.kind == AuctionKind::Fixed || auction.kind == AuctionKind::Reserved {
+            return AuctionPhase::Closed;
+        }
+
+        let elapsed = env::block_index() - auction.start_block_height;
+        if elapsed < self.auction_period {
+            AuctionPhase::Bidding
+        } else if elapsed < self.auction_period + self.reveal_period {
+            AuctionPhase::Reveal
+        } else {
+            AuctionPhase::Closed
+        }
+    }
+
+    /// Picks the close block height of a candle auction once its bidding period is over,
+    /// the standings are then computed over the bids placed before it. Returns true if the auction was closed.
+    /// The close block height is derived from the seeds of the blocks the bids were placed in, it is fixed by the end
+    /// of the bidding period and doesn't depend on the block the auction is closed in. The trust assumption is on the
+    /// validators producing the blocks of the bids: the producer of the block of the last bid can compute the close
+    /// block height before including it, and could leave the bid out or delay it to pick another outcome.
+    fn close_candle(&self, auction: &mut Auction) -> bool {
+        if auction.kind != AuctionKind::Candle || auction.close_block_height.is_some() || self.auction_phase(auction) == AuctionPhase::Bidding {
+            return false;
+        }
+
+        let close_block_height = candle_close_block_height(auction.start_block_height, self.auction_period, &auction.close_seed);
+        let mut standings = Standings::default();
+        for (bidder_account_id, bid) in auction.bids.iter() {
+            if bid.block_height < close_block_height {
+                standings.record(&bidder_account_id, bid.amount, bid.block_height);
+            }
+        }
+        auction.standings = standings;
+        auction.close_block_height = Some(close_block_height);
+        env::log(format!("Candle auction closed at block {}", close_block_height).as_bytes());
+        true
+    }
+
+    /// Returns true if the auction can be claimed and losing bids withdrawn.
+    fn is_settled(&self, auction: &Auction) -> bool {
+        if auction.kind == AuctionKind::Candle && auction.close_block_height.is_none() {
+            return false;
+        }
+        match self.auction_phase(auction) {
+            AuctionPhase::Bidding => false,
+            AuctionPhase::Reveal => self.is_fully_revealed(auction),
+            AuctionPhase::Closed => true,
+        }
+    }
+
+    /// Returns true if every bid of `auction` is revealed and no blind bid can still join it.
+    /// Blind bids don't name their auction until disclosed, so a sealed auction waits for the end of the reveal period
+    /// while a blind bid placed during the week of its name is undisclosed.
+    fn is_fully_revealed(&self, auction: &Auction) -> bool {
+        if auction.bids.len() != auction.reveals.len() {
+            return false;
+        }
+        if auction.kind != AuctionKind::Sealed {
+            return true;
+        }
+
+        // the auction started during the week of its name, the blind bids it can get were placed that week
+        let week_start_block_height = auction.start_block_height - (auction.start_block_height - self.start_block_height) % self.auction_period;
+        let start = Bound::Included((week_start_block_height, String::new()));
+        let end = Bound::Excluded((week_start_block_height + self.auction_period, String::new()));
+        self.blind_bids_by_block.range((start, end)).next().is_none()
+    }
+
+    /// Removes the `limit` oldest blind bids that can no longer be disclosed, the last auction they could join is past
+    /// its reveal period. Their deposits are refunded.
+    fn remove_expired_blind_bids(&mut self, limit: u64) {
+        let expiry_period = self.auction_period + self.reveal_period;
+        let expired: Vec<String> = self.blind_bids_by_block
+            .iter()
+            .take_while(|((block_height, _), _)| block_height + expiry_period <= env::block_index())
+            .take(limit as usize)
+            .map(|((_, key), _)| key)
+            .collect();
+        for key in expired {
+            if let Some(blind_bid) = self.blind_bids.get(&key) {
+                self.remove_blind_bid(&key, &blind_bid);
+            }
+        }
+    }
+
+    /// Removes the blind bid stored under `key` and refunds its deposit.
+    fn remove_blind_bid(&mut self, key: &str, blind_bid: &BlindBid) {
+        self.blind_bids.remove(&key.to_string());
+        self.blind_bids_by_block.remove(&(blind_bid.block_height, key.to_string()));
+        self.refund(&blind_bid.bidder_account_id, &blind_bid.bidder_account_id, blind_bid.deposit);
+    }
+
+    /// Returns what `bidder_account_id` has left to do in `auction`.
+    fn participation_status(&self, auction: &Auction, bidder_account_id: &AccountId, bid: &Bid) -> ParticipationStatus {
+        let phase = self.auction_phase(auction);
+        if phase == AuctionPhase::Bidding {
+            return ParticipationStatus::Bidding;
+        }
+
+        let is_revealed = auction.reveals.get(bidder_account_id).is_some();
+        if !is_revealed {
+            return if phase == AuctionPhase::Reveal { ParticipationStatus::NeedsReveal } else { ParticipationStatus::Done };
+        }
+
+        let is_winner = auction.is_winner(bidder_account_id);
+        if is_winner && auction.claim_status != ClaimStatus::Unclaimed {
+            return if auction.claim_status == ClaimStatus::Pending { ParticipationStatus::Won } else { ParticipationStatus::Done };
+        }
+        match (self.is_settled(auction), is_winner) {
+            (false, true) => ParticipationStatus::Won,
+            (false, false) => ParticipationStatus::Revealed,
+            (true, true) => ParticipationStatus::Claimable,
+            (true, false) if bid.amount > 0 => ParticipationStatus::Refundable,
+            (true, false) => ParticipationStatus::Withdrawn,
+        }
+    }
+
+    /// Mints the token of the claimed `account_id`.
+    fn mint_name_token(&mut self, account_id: &AccountId, name_token: NameToken) {
+        self.add_name_token_to_owner(&name_token.owner_id, account_id);
+        env::log(format!("Minted the token of {} for {}", account_id, name_token.owner_id).as_bytes());
+        self.name_tokens.insert(account_id, &name_token);
+    }
+
+    /// Transfers the token of the name `token_id` from `sender_id` to `receiver_id`, panics if `sender_id` doesn't own it.
+    fn transfer_name_token(&mut self, sender_id: &AccountId, receiver_id: &AccountId, token_id: &AccountId, memo: Option<String>) {
+        let mut name_token = self.name_tokens.get(token_id).unwrap_or_else(|| env::panic(b"Token not found"));
+        assert_eq!(&name_token.owner_id, sender_id, "Sender doesn't own the token");
+        assert_ne!(sender_id, receiver_id, "Sender and receiver should be different");
+
+        self.remove_name_token_from_owner(sender_id, token_id);
+        self.add_name_token_to_owner(receiver_id, token_id);
+        name_token.owner_id = receiver_id.to_string();
+        self.name_tokens.insert(token_id, &name_token);
+        env::log(format!("Transferred the token of {} from {} to {}{}", token_id, sender_id, receiver_id, memo.map_or(String::new(), |memo| format!(": {}", memo))).as_bytes());
+    }
+
+    /// Returns the NEP-171 view of `name_token`.
+    fn token_view(&self, token_id: String, name_token: NameToken) -> Token {
+        let extra = near_sdk::serde_json::json!({
+            "price": name_token.price.to_string(),
+            "token_id": name_token.token_id,
+            "claim_block_height": name_token.claim_block_height,
+        });
+        Token {
+            metadata: TokenMetadata {
+                title: Some(token_id.to_string()),
+                description: None,
+                media: None,
+                media_hash: None,
+                copies: Some(1),
+                issued_at: None,
+                expires_at: None,
+                starts_at: None,
+                updated_at: None,
+                extra: Some(extra.to_string()),
+                reference: None,
+                reference_hash: None,
+            },
+            token_id,
+            owner_id: name_token.owner_id,
+        }
+    }
+
+    /// Adds the token of the name `token_id` to the tokens of `owner_id`.
+    fn add_name_token_to_owner(&mut self, owner_id: &AccountId, token_id: &AccountId) {
+        let mut token_ids = self.name_tokens_per_owner.get(owner_id).unwrap_or_else(|| {
+            let mut prefix = env::sha256(owner_id.as_bytes());
+            prefix.extend_from_slice(b":o");
+            UnorderedSet::new(prefix)
+        });
+        token_ids.insert(token_id);
+        self.name_tokens_per_owner.insert(owner_id, &token_ids);
+    }
+
+    /// Removes the token of the name `token_id` from the tokens of `owner_id`.
+    fn remove_name_token_from_owner(&mut self, owner_id: &AccountId, token_id: &AccountId) {
+        if let Some(mut token_ids) = self.name_tokens_per_owner.get(owner_id) {
+            token_ids.remove(token_id);
+            if token_ids.is_empty() {
+                self.name_tokens_per_owner.remove(owner_id);
+            } else {
+                self.name_tokens_per_owner.insert(owner_id, &token_ids);
+            }
+        }
+    }
+
+    /// Records that `bidder_account_id` bid on `account_id`.
+    fn add_participation(&mut self, bidder_account_id: &AccountId, account_id: &AccountId) {
+        let mut auction_ids = self.participations.get(bidder_account_id).unwrap_or_else(|| {
+            let mut prefix = env::sha256(bidder_account_id.as_bytes());
+            prefix.extend_from_slice(b":p");
+            UnorderedSet::new(prefix)
+        });
+        auction_ids.insert(account_id);
+        self.participations.insert(bidder_account_id, &auction_ids);
+    }
+
+    /// Forgets that `bidder_account_id` bid on `account_id`, once its bid is removed or the claim of the winner is over.
+    fn remove_participation(&mut self, bidder_account_id: &AccountId, account_id: &AccountId) {
+        if let Some(mut auction_ids) = self.participations.get(bidder_account_id) {
+            auction_ids.remove(account_id);
+            if auction_ids.is_empty() {
+                self.participations.remove(bidder_account_id);
+            } else {
+                self.participations.insert(bidder_account_id, &auction_ids);
+            }
+        }
+    }
+}
+
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::assertions_on_constants)]
+mod tests {
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+
+    use super::*;
+
+    fn alice() -> AccountId {
+        "alice.near".to_string()
+    }
+    fn bob() -> AccountId {
+        "bob.near".to_string()
+    }
+    fn carol() -> AccountId {
+        "carol.near".to_string()
+    }
+    fn auctioned_id() -> AccountId {
+        "auctioned_id1.near".to_string()
+    }
+    fn auctioned_id2() -> AccountId {
+        "auctioned_id17.near".to_string()
+    }
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 2,
+            block_timestamp: 0,
+            account_balance: 3123,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn get_context2(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 1292,
+            block_timestamp: 0,
+            account_balance: 3123,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
         }
     }
 
@@ -483,9 +2699,93 @@ mod tests {
             signer_account_pk: vec![0, 1, 2],
             predecessor_account_id,
             input: vec![],
-            block_index: 4,
+            block_index: 4,
+            block_timestamp: 0,
+            account_balance: 3123,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 0,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn get_context4(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 1322,
+            block_timestamp: 0,
+            account_balance: 2123,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 1000,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn get_context5(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 1357,
+            block_timestamp: 0,
+            account_balance: 2123,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 1000,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn get_context6(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 1322,
+            block_timestamp: 0,
+            account_balance: 1234,
+            account_locked_balance: 0,
+            storage_usage: 10u64.pow(6),
+            attached_deposit: 1005,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 0,
+        }
+    }
+
+    fn get_context7(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            current_account_id: alice(),
+            signer_account_id: bob(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 1322,
             block_timestamp: 0,
-            account_balance: 3123,
+            account_balance: 1234,
             account_locked_balance: 0,
             storage_usage: 10u64.pow(6),
             attached_deposit: 0,
@@ -497,247 +2797,2113 @@ mod tests {
         }
     }
 
-    fn get_context4(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 1322,
-            block_timestamp: 0,
-            account_balance: 2123,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 1000,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
+    fn get_context8(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            attached_deposit: 1000,
+            ..get_context2(predecessor_account_id)
+        }
+    }
+
+    fn get_context9(predecessor_account_id: AccountId) -> VMContext {
+        VMContext {
+            block_index: 1300,
+            attached_deposit: 1005,
+            ..get_context2(predecessor_account_id)
+        }
+    }
+
+    fn set_promise_result(context: VMContext, promise_result: PromiseResult) {
+        let storage = env::take_blockchain_interface().unwrap().as_mut_mocked_blockchain().unwrap().take_storage();
+        env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+            context,
+            Default::default(),
+            Default::default(),
+            vec![promise_result],
+            storage,
+            Default::default(),
+        )));
+    }
+
+    #[test]
+    fn bid_with_commitment() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), true);
+    }
+
+    #[test]
+    fn account_id_is_open_for_auction() {
+        let context = get_context(bob());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), true);
+    }
+
+    #[test]
+    fn is_not_open_for_auction_min() {
+        let context = get_context(alice());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context3(alice());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), false);
+    }
+
+    #[test]
+    fn is_not_open_for_auction_max() {
+        let context = get_context(alice());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context4(alice());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), false);
+    }
+
+    #[test]
+    fn bidder_already_bid() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let commitment2 = "2s7YSBAHei2";
+        assert_eq!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()), false);
+    }
+
+    #[test]
+    fn auction_is_expired() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context4(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSBAHei2";
+
+        assert_eq!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()), false);
+    }
+
+    #[test]
+    fn reveal_the_amount() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+
+        assert_eq!(contract.reveal(auctioned_id(), masked_amount, salt), true);
+    }
+
+    #[test]
+    fn dont_reveal_if_auction_in_progress() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+
+        assert_eq!(contract.reveal(auctioned_id(), masked_amount, salt), false);
+    }
+
+    #[test]
+    fn withdraw_after_all_revealed() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+
+        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+        contract.reveal(auctioned_id(), masked_amount, salt);
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 1005;
+        let salt2: String = "123".to_string();
+        contract.reveal(auctioned_id(), masked_amount2, salt2);
+
+        // bob wins, only carol gets its bid back
+        assert_eq!(contract.withdraw(auctioned_id()), false);
+
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        assert_eq!(contract.withdraw(auctioned_id()), true);
+    }
+
+    #[test]
+    fn withdraw_after_reveal_period_expired() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+
+        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
+
+        let context4 = get_context5(carol());
+        testing_env!(context4);
+        
+        assert_eq!(contract.withdraw(auctioned_id()), true);
+    }
+
+    #[test]
+    fn withdraw_when_reveal_in_progress() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+
+        assert_eq!(contract.withdraw(auctioned_id()), false);
+    }
+
+    #[test]
+    fn withdraw_but_reveal_in_progress_and_not_all_bidders_revealed() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+
+        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 1005;
+        let salt2: String = "123".to_string();
+        contract.reveal(auctioned_id(), masked_amount2, salt2);
+
+        assert_eq!(contract.withdraw(auctioned_id()), false);
+    }
+
+    #[test]
+    fn check_contract_balance_after_multiple_withdraws() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+
+        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount, salt) {
+            assert!(false);
+        }
+
+        if !contract.reveal(auctioned_id(), masked_amount, "123".to_string()) {
+            assert!(false);
+        }
+        
+        // carol wins, its bid stays with the registrar until it claims
+        if contract.withdraw(auctioned_id()) {
+            assert!(false);
+        }
+
+        assert_eq!( env::account_balance() == 2123, true);
+    }
+
+    #[test]
+    fn claim_the_account() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount, salt) {
+            assert!(false);
+        }
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 1005;
+        let salt2: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
+            assert!(false);
+        }
+
+        if !contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())) {
+            assert!(false);
+        }
+
+        // carol is refunded 1000 and bob pays 1000 out of 1005
+        assert_eq!( env::account_balance() == 1234, true);
+    }
+
+    #[test]
+    fn claim_fails_if_the_highest_bid_is_0() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2ESvwk";
+        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2ESvwk";
+        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context4 = get_context7(carol());
+        testing_env!(context4);
+        let masked_amount: Balance = 0;
+        let salt: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount, salt) {
+            assert!(false);
+        }
+
+        let context5 = get_context7(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 0;
+        let salt2: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
+            assert!(false);
+        }
+
+        assert_eq!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())), false);
+    }
+
+    #[test]
+    fn winner_pays_highest_bid_if_second_highest_bid_is_0() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2ESvwk";
+        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
+            assert!(false);
+        }
+
+        let context4 = get_context7(carol());
+        testing_env!(context4);
+        let masked_amount: Balance = 0;
+        let salt: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount, salt) {
+            assert!(false);
+        }
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 1005;
+        let salt2: String = "123".to_string();
+        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
+            assert!(false);
+        }
+
+        if !contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())) {
+            assert!(false);
+        }
+
+        assert_eq!( env::account_balance() == 2239, true);
+    }
+
+    #[test]
+    fn list_auctions_filtered_by_phase() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let auctions = contract.list_auctions(0, 10, None);
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].account_id, auctioned_id());
+        assert_eq!(auctions[0].phase, AuctionPhase::Bidding);
+        assert_eq!(auctions[0].num_bids, 1);
+        assert_eq!(contract.list_auctions(0, 10, Some(AuctionPhase::Reveal)).len(), 0);
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+        assert_eq!(contract.list_auctions(0, 10, Some(AuctionPhase::Reveal)).len(), 1);
+        assert_eq!(contract.list_auctions(1, 10, None).len(), 0);
+        assert_eq!(contract.list_auctions(0, 0, None).len(), 0);
+    }
+
+    #[test]
+    fn list_bids_hides_commitments_while_bidding() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        let bids = contract.list_bids(auctioned_id(), 0, 10);
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].bidder_account_id, carol());
+        assert_eq!(bids[0].commitment, None);
+        assert_eq!(bids[0].amount, None);
+        assert_eq!(contract.list_bids(auctioned_id(), 1, 10).len(), 1);
+        assert_eq!(contract.list_bids(bob(), 0, 10).len(), 0);
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+        assert!(contract.reveal(auctioned_id(), masked_amount, salt));
+
+        let bids = contract.list_bids(auctioned_id(), 0, 1);
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].commitment, Some(commitment.to_string()));
+        assert_eq!(bids[0].amount, Some(U128(1000)));
+    }
+
+    #[test]
+    fn account_activity_follows_the_auction() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        let activity = contract.get_account_activity(carol());
+        assert_eq!(activity, vec![ParticipationView { account_id: auctioned_id(), status: ParticipationStatus::Bidding }]);
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert_eq!(contract.get_account_activity(carol())[0].status, ParticipationStatus::NeedsReveal);
+        let masked_amount: Balance = 1000;
+        let salt: String = "123".to_string();
+        assert!(contract.reveal(auctioned_id(), masked_amount, salt));
+        assert_eq!(contract.get_account_activity(carol())[0].status, ParticipationStatus::Won);
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        let masked_amount2: Balance = 1005;
+        let salt2: String = "123".to_string();
+        assert!(contract.reveal(auctioned_id(), masked_amount2, salt2));
+        assert_eq!(contract.get_account_activity(carol())[0].status, ParticipationStatus::Refundable);
+        assert_eq!(contract.get_account_activity(bob())[0].status, ParticipationStatus::Claimable);
+
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        assert!(contract.withdraw(auctioned_id()));
+        assert_eq!(contract.get_account_activity(carol())[0].status, ParticipationStatus::Withdrawn);
+        assert_eq!(contract.get_account_activity(bob()).len(), 1);
+    }
+
+    #[test]
+    fn account_activity_for_unrevealed_bid() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context5(carol());
+        testing_env!(context3);
+        assert_eq!(contract.get_account_activity(carol())[0].status, ParticipationStatus::Done);
+        assert_eq!(contract.get_account_activity(bob()), vec![]);
+    }
+
+    #[test]
+    fn withdraw_all_loosing_bids() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id2(), commitment2.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id2(), commitment.as_bytes().to_vec()));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        let context5 = get_context4(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id2(), 1000, "123".to_string()));
+
+        // bob didn't reveal for auctioned_id yet
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        assert!(!contract.withdraw_all(10));
+
+        let context7 = get_context6(carol());
+        testing_env!(context7);
+        assert!(contract.reveal(auctioned_id2(), 1005, "123".to_string()));
+        let context8 = get_context6(bob());
+        testing_env!(context8);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        let context9 = get_context7(carol());
+        testing_env!(context9);
+        assert!(!contract.withdraw_all(0));
+        assert!(contract.withdraw_all(10));
+        assert_eq!(env::account_balance(), 234);
+        let activity = contract.get_account_activity(carol());
+        assert_eq!(activity.len(), 2);
+        assert!(activity.contains(&ParticipationView { account_id: auctioned_id(), status: ParticipationStatus::Withdrawn }));
+        assert!(activity.contains(&ParticipationView { account_id: auctioned_id2(), status: ParticipationStatus::Claimable }));
+
+        // the won auction is left for claim
+        assert!(!contract.withdraw_all(10));
+    }
+
+    #[test]
+    fn withdraw_all_without_bids() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        assert!(!contract.withdraw_all(10));
+    }
+
+    #[test]
+    fn claim_is_done_once_the_account_is_created() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context4 = get_context7(carol());
+        testing_env!(context4);
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(contract.claim(auctioned_id(), public_key.clone()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Pending);
+
+        // the name can't be claimed twice and the winner can't withdraw the payment
+        assert!(!contract.claim(auctioned_id(), public_key));
+        assert!(!contract.withdraw(auctioned_id()));
+
+        set_promise_result(get_context7(alice()), PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), carol()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Claimed);
+        assert_eq!(contract.get_account_activity(carol()), vec![]);
+        assert_eq!(env::account_balance(), 1234);
+    }
+
+    #[test]
+    fn failed_claim_refunds_the_winner() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context4 = get_context7(carol());
+        testing_env!(context4);
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(contract.claim(auctioned_id(), public_key.clone()));
+
+        set_promise_result(get_context7(alice()), PromiseResult::Failed);
+        assert!(!contract.on_claim(auctioned_id(), carol()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Failed);
+        assert_eq!(env::account_balance(), 234);
+
+        // the callback is applied only once
+        assert!(!contract.on_claim(auctioned_id(), carol()));
+        assert_eq!(env::account_balance(), 234);
+
+        let context5 = get_context7(carol());
+        testing_env!(context5);
+        assert!(!contract.claim(auctioned_id(), public_key));
+        assert!(!contract.withdraw_all(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "Callback can only be called by the registrar")]
+    fn on_claim_is_private() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        set_promise_result(get_context7(carol()), PromiseResult::Successful(vec![]));
+        contract.on_claim(auctioned_id(), carol());
+    }
+
+    #[test]
+    fn failed_refund_is_credited_to_the_balance() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        set_promise_result(get_context7(alice()), PromiseResult::Successful(vec![]));
+        assert!(contract.on_refund(carol(), U128(1000)));
+        assert_eq!(contract.get_balance(carol()), U128(0));
+
+        set_promise_result(get_context7(alice()), PromiseResult::Failed);
+        assert!(!contract.on_refund(carol(), U128(1000)));
+        assert!(!contract.on_refund(carol(), U128(5)));
+        assert_eq!(contract.get_balance(carol()), U128(1005));
+    }
+
+    #[test]
+    fn withdraw_balance_to_another_account() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context7(carol());
+        testing_env!(context2);
+        assert!(!contract.withdraw_balance(bob()));
+
+        set_promise_result(get_context7(alice()), PromiseResult::Failed);
+        assert!(!contract.on_refund(carol(), U128(1000)));
+
+        let context3 = get_context7(carol());
+        testing_env!(context3);
+        assert!(contract.withdraw_balance(bob()));
+        assert_eq!(contract.get_balance(carol()), U128(0));
+        assert_eq!(env::account_balance(), 234);
+        assert!(!contract.withdraw_balance(bob()));
+    }
+
+    /// Settles the auction over the revealed `(bidder, amount, bid block height)` entries, in the order they are revealed.
+    fn settle(reveals: &[(AccountId, Balance, BlockHeight)]) -> Option<Settlement> {
+        let mut standings = Standings::default();
+        for (account_id, amount, block_height) in reveals {
+            standings.record(account_id, *amount, *block_height);
+        }
+        standings.settlement()
+    }
+
+    fn permutations(reveals: &[(AccountId, Balance, BlockHeight)]) -> Vec<Vec<(AccountId, Balance, BlockHeight)>> {
+        if reveals.len() <= 1 {
+            return vec![reveals.to_vec()];
+        }
+        let mut result = vec![];
+        for index in 0..reveals.len() {
+            let mut rest = reveals.to_vec();
+            let first = rest.remove(index);
+            for mut permutation in permutations(&rest) {
+                permutation.insert(0, first.clone());
+                result.push(permutation);
+            }
+        }
+        result
+    }
+
+    fn assert_settles_in_any_order(reveals: &[(AccountId, Balance, BlockHeight)], expected: Option<Settlement>) {
+        let permutations = permutations(reveals);
+        assert!(permutations.len() > 1 || reveals.len() <= 1);
+        for permutation in permutations {
+            assert_eq!(settle(&permutation), expected, "reveal order {:?}", permutation);
+        }
+    }
+
+    #[test]
+    fn settle_highest_bid_pays_second_highest() {
+        let reveals = vec![
+            (alice(), 1000, 10),
+            (bob(), 1005, 11),
+            (carol(), 1003, 12),
+            ("dave.near".to_string(), 999, 13),
+        ];
+        assert_settles_in_any_order(&reveals, Some(Settlement { winning_account_id: bob(), highest_bid: 1005, price: 1003 }));
+    }
+
+    #[test]
+    fn settle_tie_goes_to_earliest_bid() {
+        let reveals = vec![(alice(), 1000, 12), (bob(), 1005, 11), (carol(), 1005, 10), ("dave.near".to_string(), 1, 9)];
+        assert_settles_in_any_order(&reveals, Some(Settlement { winning_account_id: carol(), highest_bid: 1005, price: 1005 }));
+
+        // bids in the same block go to the lowest account id
+        let reveals = vec![(carol(), 1005, 10), (bob(), 1005, 10), (alice(), 1000, 10)];
+        assert_settles_in_any_order(&reveals, Some(Settlement { winning_account_id: bob(), highest_bid: 1005, price: 1005 }));
+    }
+
+    #[test]
+    fn settle_single_non_zero_bid_pays_its_amount() {
+        let reveals = vec![(alice(), 0, 10), (bob(), 1005, 11), (carol(), 0, 9)];
+        assert_settles_in_any_order(&reveals, Some(Settlement { winning_account_id: bob(), highest_bid: 1005, price: 1005 }));
+        assert_settles_in_any_order(&[(bob(), 1005, 11)], Some(Settlement { winning_account_id: bob(), highest_bid: 1005, price: 1005 }));
+    }
+
+    #[test]
+    fn settle_without_non_zero_bids() {
+        assert_settles_in_any_order(&[(alice(), 0, 10), (bob(), 0, 11)], None);
+        assert_settles_in_any_order(&[], None);
+    }
+
+    #[test]
+    fn claim_refunds_the_winner_above_the_second_price() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        // bob bids first but reveals last
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context5 = get_context4(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        // the tie goes to bob, carol can't claim
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(!contract.claim(auctioned_id(), public_key.clone()));
+
+        let context7 = get_context7(bob());
+        testing_env!(context7);
+        assert!(contract.claim(auctioned_id(), public_key));
+        assert_eq!(env::account_balance(), 234);
+    }
+
+    #[test]
+    fn standings_ignore_repeated_reveals() {
+        let mut standings = Standings::default();
+        standings.record(&alice(), 1000, 10);
+        standings.record(&alice(), 1000, 10);
+        assert_eq!(standings.settlement(), Some(Settlement { winning_account_id: alice(), highest_bid: 1000, price: 1000 }));
+
+        standings.record(&bob(), 900, 11);
+        standings.record(&bob(), 900, 11);
+        standings.record(&carol(), 0, 9);
+        assert_eq!(standings.settlement(), Some(Settlement { winning_account_id: alice(), highest_bid: 1000, price: 900 }));
+    }
+
+    #[test]
+    fn list_auctions_shows_the_winner_once_revealed() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        let context4 = get_context6(bob());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, None);
+        assert_eq!(auction.price, None);
+
+        let context5 = get_context4(carol());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, Some(bob()));
+        assert_eq!(auction.price, Some(U128(1000)));
+    }
+
+    #[test]
+    fn open_auction_refunds_the_outbid_bidder() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new_with_auction_kind(30, 35, AuctionKind::Open, U128(5));
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(!contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert!(!contract.bid_open(auctioned_id()));
+
+        let context3 = get_context8(bob());
+        testing_env!(context3);
+        assert!(contract.bid_open(auctioned_id()));
+
+        // the increment is below min_increment, the deposit is refunded
+        let context4 = get_context9(carol());
+        testing_env!(VMContext { attached_deposit: 1004, ..context4 });
+        assert!(!contract.bid_open(auctioned_id()));
+        assert_eq!(env::account_balance(), 3123);
+
+        let context5 = get_context9(carol());
+        testing_env!(context5);
+        assert!(contract.bid_open(auctioned_id()));
+        assert_eq!(env::account_balance(), 3128);
+        assert!(!contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert_eq!(contract.list_bids(auctioned_id(), 0, 10), vec![BidView { bidder_account_id: carol(), commitment: None, amount: Some(U128(1005)) }]);
+        assert_eq!(contract.get_account_activity(bob()), vec![]);
+
+        // the winner pays its own bid
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        assert!(!contract.reveal(auctioned_id(), 0, "123".to_string()));
+        assert!(!contract.withdraw(auctioned_id()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Open);
+        assert_eq!(auction.winning_account_id, Some(carol()));
+        assert_eq!(auction.price, Some(U128(1005)));
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(env::account_balance(), 1234);
+    }
+
+    #[test]
+    fn owner_sets_the_auction_kind_per_name() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        assert_eq!(contract.get_auction_kind(auctioned_id()), AuctionKind::Sealed);
+
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        assert!(!contract.set_auction_kind(auctioned_id(), Some(AuctionKind::Open)));
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.set_auction_kind(auctioned_id(), Some(AuctionKind::Open)));
+        assert_eq!(contract.get_auction_kind(auctioned_id()), AuctionKind::Open);
+        assert_eq!(contract.get_auction_kind(auctioned_id2()), AuctionKind::Sealed);
+
+        let context4 = get_context8(bob());
+        testing_env!(context4);
+        let commitment = "2s7YSBAHei";
+        assert!(!contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert!(contract.bid_open(auctioned_id()));
+        assert!(!contract.bid_open(auctioned_id2()));
+        assert!(contract.bid(auctioned_id2(), commitment.as_bytes().to_vec()));
+
+        // auctions that already started keep their format
+        let context5 = get_context2(carol());
+        testing_env!(context5);
+        assert!(contract.set_auction_kind(auctioned_id(), None));
+        assert_eq!(contract.get_auction_kind(auctioned_id()), AuctionKind::Sealed);
+        assert_eq!(contract.list_auctions(0, 10, None)[0].kind, AuctionKind::Open);
+    }
+
+    #[test]
+    fn buy_in_dutch_auction_after_the_week() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let dutch_auction = DutchAuctionConfig { start_price: U128(2000), end_price: U128(1000), duration: 100 };
+
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        assert!(!contract.set_dutch_auction(Some(dutch_auction.clone())));
+        assert_eq!(contract.get_dutch_price(auctioned_id()), None);
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.set_dutch_auction(Some(dutch_auction)));
+
+        // the name is still on the market
+        assert_eq!(contract.get_dutch_price(auctioned_id()), None);
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        let context4 = get_context8(bob());
+        testing_env!(context4);
+        assert!(!contract.buy(auctioned_id(), public_key.clone()));
+        assert_eq!(env::account_balance(), 3123);
+
+        let context5 = get_context7(bob());
+        testing_env!(VMContext { block_index: 1322, ..context5 });
+        assert_eq!(contract.get_dutch_price(auctioned_id()), Some(U128(2000)));
+        let context6 = get_context7(bob());
+        testing_env!(VMContext { block_index: 1500, ..context6 });
+        assert_eq!(contract.get_dutch_price(auctioned_id()), Some(U128(1000)));
+
+        // the price is 1500 half way, the deposit above it is refunded
+        let context7 = get_context7(bob());
+        testing_env!(VMContext { block_index: 1372, attached_deposit: 1400, ..context7 });
+        assert_eq!(contract.get_dutch_price(auctioned_id()), Some(U128(1500)));
+        assert!(!contract.buy(auctioned_id(), public_key.clone()));
+
+        let context8 = get_context7(bob());
+        testing_env!(VMContext { block_index: 1372, attached_deposit: 2000, ..context8 });
+        assert!(contract.buy(auctioned_id(), public_key.clone()));
+        assert_eq!(env::account_balance(), 3234 - 500);
+        assert_eq!(contract.get_dutch_price(auctioned_id()), None);
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Dutch);
+        assert_eq!(auction.claim_status, ClaimStatus::Pending);
+        assert_eq!(auction.price, Some(U128(1500)));
+
+        set_promise_result(get_context7(alice()), PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+
+        let context9 = get_context7(carol());
+        testing_env!(VMContext { block_index: 1372, attached_deposit: 2000, ..context9 });
+        assert!(!contract.buy(auctioned_id(), public_key));
+    }
+
+    #[test]
+    fn names_with_bids_are_not_in_dutch_auction() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        assert!(contract.set_dutch_auction(Some(DutchAuctionConfig { start_price: U128(2000), end_price: U128(1000), duration: 100 })));
+        assert!(!contract.set_dutch_auction(Some(DutchAuctionConfig { start_price: U128(1000), end_price: U128(2000), duration: 100 })));
+        assert!(!contract.set_auction_kind(auctioned_id(), Some(AuctionKind::Dutch)));
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context7(bob());
+        testing_env!(VMContext { block_index: 1500, attached_deposit: 2000, ..context3 });
+        assert_eq!(contract.get_dutch_price(auctioned_id()), None);
+        assert!(!contract.buy(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+    }
+
+    #[test]
+    fn candle_auction_ignores_bids_after_the_close_block() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new_with_auction_kind(30, 35, AuctionKind::Candle, U128(5));
+
+        let context2 = get_context8(bob());
+        testing_env!(context2);
+        assert!(contract.bid_open(auctioned_id()));
+        assert!(!contract.bid_open(auctioned_id()));
+
+        // the outbid amount is kept until the auction is closed
+        let context3 = get_context9(carol());
+        testing_env!(VMContext { random_seed: vec![7], ..context3 });
+        assert!(contract.bid_open(auctioned_id()));
+        assert!(!contract.close_auction(auctioned_id()));
+        assert!(!contract.withdraw(auctioned_id()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].winning_account_id, None);
+
+        // the seeds of the bids close the auction at block 1296 whatever the seed of the closing block, carol's bid came too late
+        let context4 = get_context7(carol());
+        testing_env!(VMContext { random_seed: vec![0], ..context4 });
+        assert!(contract.close_auction(auctioned_id()));
+        assert!(!contract.close_auction(auctioned_id()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Candle);
+        assert_eq!(auction.close_block_height, Some(1296));
+        assert_eq!(auction.winning_account_id, Some(bob()));
+        assert_eq!(auction.price, Some(U128(1000)));
+        assert!(contract.withdraw(auctioned_id()));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context5 = get_context7(bob());
+        testing_env!(context5);
+        assert!(!contract.withdraw(auctioned_id()));
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+    }
+
+    #[test]
+    fn candle_auction_closes_on_claim() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new_with_auction_kind(30, 35, AuctionKind::Candle, U128(5));
+
+        let context2 = get_context8(bob());
+        testing_env!(context2);
+        assert!(contract.bid_open(auctioned_id()));
+
+        let context3 = get_context9(carol());
+        testing_env!(VMContext { random_seed: vec![4], ..context3 });
+        assert!(contract.bid_open(auctioned_id()));
+
+        // the seeds of the bids close the auction at block 1301, after carol's bid
+        let context4 = get_context7(carol());
+        testing_env!(context4);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.close_block_height, Some(1301));
+        assert_eq!(auction.winning_account_id, Some(carol()));
+        assert_eq!(auction.price, Some(U128(1005)));
+        assert_eq!(contract.get_account_activity(bob())[0].status, ParticipationStatus::Withdrawn);
+    }
+
+    #[test]
+    fn register_a_long_name_at_its_tier_price() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let long_id = "a_long_name_that_is_never_auctioned.near".to_string();
+        let register_prices = vec![PriceTier { min_length: 10, price: U128(1000) }, PriceTier { min_length: 32, price: U128(100) }];
+        assert!(contract.set_register_prices(register_prices.clone()));
+
+        let context2 = get_context8(bob());
+        testing_env!(context2);
+        assert!(!contract.set_register_prices(register_prices));
+        assert_eq!(contract.get_register_price(long_id.clone()), Some(U128(100)));
+        assert_eq!(contract.get_register_price(auctioned_id()), None);
+        assert_eq!(contract.get_register_price("a.near".to_string()), None);
+
+        // names in or due for auction, and names without a tier, can't be registered
+        assert!(!contract.register(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert!(!contract.register("a.near".to_string(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        // the deposit above the price is refunded
+        let context3 = get_context8(bob());
+        testing_env!(context3);
+        assert!(contract.register(long_id.clone(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(env::account_balance(), 3223);
+        assert!(!contract.register(long_id.clone(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.account_id, long_id);
+        assert_eq!(auction.kind, AuctionKind::Fixed);
+        assert_eq!(auction.claim_status, ClaimStatus::Pending);
+        assert_eq!(auction.winning_account_id, Some(bob()));
+        assert_eq!(auction.price, Some(U128(100)));
+    }
+
+    #[test]
+    fn register_a_name_released_from_the_schedule() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        assert!(contract.set_register_prices(vec![PriceTier { min_length: 10, price: U128(1000) }]));
+
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        // the week is over, only the name nobody bid on can be registered
+        let context3 = get_context7(bob());
+        testing_env!(VMContext { attached_deposit: 1000, ..context3 });
+        assert!(!contract.register(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(contract.get_register_price(auctioned_id2()), Some(U128(1000)));
+        assert!(contract.register(auctioned_id2(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(contract.get_account_activity(bob()).len(), 2);
+    }
+
+    #[test]
+    fn reserved_name_is_claimed_by_the_assigned_account() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        assert!(contract.reserve_name(auctioned_id(), None));
+        assert!(contract.is_reserved(auctioned_id()));
+        assert_eq!(contract.get_reserved_for(auctioned_id()), None);
+
+        // reserved names are excluded from the auctions
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        assert!(!contract.reserve_name(auctioned_id(), Some(bob())));
+        let commitment = "2s7YSBAHei";
+        assert!(!contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.reserve_name(auctioned_id(), Some(bob())));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context4 = get_context2(bob());
+        testing_env!(context4);
+        assert_eq!(contract.get_reserved_for(auctioned_id()), Some(bob()));
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Reserved);
+        assert_eq!(auction.claim_status, ClaimStatus::Pending);
+
+        // a failed claim can be retried
+        let context5 = get_context2(alice());
+        set_promise_result(context5, PromiseResult::Failed);
+        assert!(!contract.on_claim(auctioned_id(), bob()));
+
+        let context6 = get_context2(bob());
+        testing_env!(context6);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context7 = get_context2(alice());
+        set_promise_result(context7, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Claimed);
+    }
+
+    #[test]
+    fn released_name_goes_back_to_the_market() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let long_id = "a_long_name_that_is_never_auctioned.near".to_string();
+        assert!(contract.set_register_prices(vec![PriceTier { min_length: 10, price: U128(1000) }]));
+        assert!(contract.reserve_name(auctioned_id(), None));
+        assert!(contract.reserve_name(long_id.clone(), None));
+
+        let context2 = get_context8(bob());
+        testing_env!(context2);
+        assert!(!contract.unreserve_name(auctioned_id()));
+        assert_eq!(contract.get_register_price(long_id.clone()), None);
+        assert!(!contract.register(long_id.clone(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.unreserve_name(auctioned_id()));
+        assert!(!contract.unreserve_name(auctioned_id()));
+        assert!(!contract.is_reserved(auctioned_id()));
+
+        let context4 = get_context2(bob());
+        testing_env!(context4);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn claim_a_pre_allocated_name_with_a_proof() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let leaf = allocation_leaf(&auctioned_id(), &bob());
+        let sibling = allocation_leaf(&auctioned_id2(), &alice());
+        let uncle = allocation_leaf(&"pre_allocated.near".to_string(), &carol());
+        let root = merkle_parent(&merkle_parent(&leaf, &sibling), &uncle);
+        let proof = vec![Base64VecU8(sibling.clone()), Base64VecU8(uncle.clone())];
+
+        // the pre-allocation is disabled until the owner publishes the root
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        assert!(!contract.claim_allocated(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), proof.clone()));
+        assert!(!contract.set_allocation_root(Some(Base64VecU8(root.clone()))));
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.set_allocation_root(Some(Base64VecU8(root.clone()))));
+        assert_eq!(contract.get_allocation_root(), Some(Base64VecU8(root)));
+        assert!(!contract.claim_allocated(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), proof.clone()));
+
+        let context4 = get_context2(bob());
+        testing_env!(context4);
+        assert!(!contract.claim_allocated(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), vec![Base64VecU8(uncle)]));
+        assert!(contract.claim_allocated(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), proof.clone()));
+        assert!(!contract.claim_allocated(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), proof));
+
+        let context5 = get_context2(alice());
+        set_promise_result(context5, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Reserved);
+        assert_eq!(auction.claim_status, ClaimStatus::Claimed);
+
+        // the name was claimed, it isn't on the market anymore
+        let context6 = get_context2(carol());
+        testing_env!(context6);
+        let commitment = "2s7YSBAHei";
+        assert!(!contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn claim_with_function_call_keys_and_a_contract() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        assert!(contract.reserve_name(auctioned_id(), Some(bob())));
+
+        let ed25519_key = Base58PublicKey([vec![0], from_hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")].concat());
+        // the generator point of secp256k1
+        let secp256k1_key = Base58PublicKey([
+            vec![1],
+            from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8"),
+        ].concat());
+        let function_call_key = AccountKey {
+            public_key: secp256k1_key,
+            permission: Some(FunctionCallPermission { allowance: Some(U128(100)), receiver_id: alice(), method_names: vec!["bid".to_string(), "reveal".to_string()] }),
+        };
+        let setup = AccountSetup {
+            keys: vec![AccountKey { public_key: ed25519_key.clone(), permission: None }, function_call_key],
+            contract: Some(ContractSetup { code: Base64VecU8(vec![0, 97, 115, 109]), init_method_name: Some("new".to_string()), init_args: Some(Base64VecU8(b"{}".to_vec())) }),
+            initial_balance: None,
+        };
+
+        // the keys, the receiver and the method names are validated up front
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        let invalid_key = AccountKey { public_key: Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec()), permission: None };
+        assert!(!contract.claim_with_setup(auctioned_id(), AccountSetup { keys: vec![invalid_key], contract: None, initial_balance: None }));
+        for public_key in [[vec![0], vec![2; 32]].concat(), [vec![1], vec![2; 64]].concat()] {
+            let off_curve_key = AccountKey { public_key: Base58PublicKey(public_key), permission: None };
+            assert!(!contract.claim_with_setup(auctioned_id(), AccountSetup { keys: vec![off_curve_key], contract: None, initial_balance: None }));
         }
+        let zero_allowance = AccountKey {
+            public_key: ed25519_key.clone(),
+            permission: Some(FunctionCallPermission { allowance: Some(U128(0)), receiver_id: alice(), method_names: vec![] }),
+        };
+        assert!(!contract.claim_with_setup(auctioned_id(), AccountSetup { keys: vec![zero_allowance], contract: None, initial_balance: None }));
+        let invalid_method_names = AccountKey {
+            public_key: ed25519_key,
+            permission: Some(FunctionCallPermission { allowance: None, receiver_id: alice(), method_names: vec!["bid,reveal".to_string()] }),
+        };
+        assert!(!contract.claim_with_setup(auctioned_id(), AccountSetup { keys: vec![invalid_method_names], contract: None, initial_balance: None }));
+        assert!(!contract.claim_with_setup(auctioned_id(), AccountSetup { keys: vec![], contract: None, initial_balance: None }));
+        assert!(env::created_receipts().is_empty());
+
+        assert!(contract.claim_with_setup(auctioned_id(), setup));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let actions = receipts[0]["actions"].as_array().unwrap();
+        assert_eq!(receipts[0]["receiver_id"], auctioned_id());
+        assert_eq!(actions.len(), 5);
+        assert_eq!(actions[0], "CreateAccount");
+        assert!(actions[1].get("AddKeyWithFullAccess").is_some());
+        assert_eq!(actions[2]["AddKeyWithFunctionCall"]["allowance"], 100);
+        assert_eq!(actions[2]["AddKeyWithFunctionCall"]["receiver_id"], alice());
+        assert_eq!(actions[3]["DeployContract"]["code"], near_sdk::serde_json::json!([0, 97, 115, 109]));
+        assert_eq!(actions[4]["FunctionCall"]["method_name"], "new");
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Pending);
     }
 
-    fn get_context5(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 1357,
-            block_timestamp: 0,
-            account_balance: 2123,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 1000,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
-        }
+    #[test]
+    fn claim_funds_the_initial_balance() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        // bob doesn't pay 5 out of its bid, the initial balance is above that and the deposit
+        let key = Base58PublicKey([vec![0], vec![1; 32]].concat());
+        let setup = |initial_balance| AccountSetup {
+            keys: vec![AccountKey { public_key: key.clone(), permission: None }],
+            contract: None,
+            initial_balance: Some(U128(initial_balance)),
+        };
+        let context6 = get_context7(bob());
+        testing_env!(VMContext { attached_deposit: 100, ..context6 });
+        assert!(!contract.claim_with_setup(auctioned_id(), setup(106)));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], bob());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 100);
+
+        let context7 = get_context7(bob());
+        testing_env!(VMContext { attached_deposit: 100, ..context7 });
+        assert!(contract.claim_with_setup(auctioned_id(), setup(105)));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let create_receipt = receipts.as_array().unwrap().iter().find(|receipt| receipt["receiver_id"] == auctioned_id()).unwrap();
+        assert_eq!(create_receipt["actions"][1]["Transfer"]["deposit"], 105);
+        assert!(receipts.as_array().unwrap().iter().all(|receipt| receipt["receiver_id"] != bob()));
+
+        // the bid and the initial balance are refunded if the account creation fails
+        let context8 = get_context7(alice());
+        set_promise_result(context8, PromiseResult::Failed);
+        assert!(!contract.on_claim(auctioned_id(), bob()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let refunds: Vec<_> = receipts.as_array().unwrap().iter()
+            .filter(|receipt| receipt["receiver_id"] == bob())
+            .map(|receipt| receipt["actions"][0]["Transfer"]["deposit"].clone())
+            .collect();
+        assert_eq!(refunds, vec![1000, 105]);
     }
 
-    fn get_context6(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 1322,
-            block_timestamp: 0,
-            account_balance: 1234,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 1005,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
-        }
+    #[test]
+    fn winner_assigns_the_claim() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+        assert!(!contract.assign_claim(auctioned_id(), alice()));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        // only the winner assigns the claim, it can change the assignee
+        let context6 = get_context7(carol());
+        testing_env!(context6);
+        assert!(!contract.assign_claim(auctioned_id(), carol()));
+
+        let context7 = get_context7(bob());
+        testing_env!(context7);
+        assert!(contract.assign_claim(auctioned_id(), carol()));
+        assert!(contract.assign_claim(auctioned_id(), alice()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_assignee, Some(alice()));
+
+        // the right to claim is transferred, the winner can't claim anymore
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context8 = get_context7(carol());
+        testing_env!(context8);
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        // the winner keeps the refunds of the auction
+        let context9 = get_context7(alice());
+        testing_env!(context9);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let refund = receipts.as_array().unwrap().iter().find(|receipt| receipt["receiver_id"] == bob()).unwrap();
+        assert_eq!(refund["actions"][0]["Transfer"]["deposit"], 5);
+        assert_eq!(contract.list_auctions(0, 10, None)[0].claim_status, ClaimStatus::Pending);
+
+        let context10 = get_context7(bob());
+        testing_env!(context10);
+        assert!(!contract.assign_claim(auctioned_id(), bob()));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
     }
 
-    fn get_context7(predecessor_account_id: AccountId) -> VMContext {
-        VMContext {
-            current_account_id: alice(),
-            signer_account_id: bob(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 1322,
-            block_timestamp: 0,
-            account_balance: 1234,
-            account_locked_balance: 0,
-            storage_usage: 10u64.pow(6),
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 0,
-        }
+    #[test]
+    fn bid_on_behalf_of_a_beneficiary() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid_with_beneficiary(auctioned_id(), commitment2.as_bytes().to_vec(), Some(alice())));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        // the win belongs to alice, bob paid for it
+        let context6 = get_context7(bob());
+        testing_env!(context6);
+        assert_eq!(contract.list_auctions(0, 10, None)[0].winning_account_id, Some(alice()));
+        assert!(!contract.assign_claim(auctioned_id(), bob()));
+        assert!(!contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        // the refunds go back to the bidders who paid
+        let context7 = get_context7(alice());
+        testing_env!(context7);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let refund = |account_id: AccountId| receipts.as_array().unwrap().iter()
+            .find(|receipt| receipt["receiver_id"] == account_id)
+            .map(|receipt| receipt["actions"][0]["Transfer"]["deposit"].clone());
+        assert_eq!(refund(bob()), Some(5.into()));
+        assert_eq!(refund(carol()), Some(1000.into()));
+
+        // the bid is refunded to bob if the account creation fails
+        let context8 = get_context7(alice());
+        set_promise_result(context8, PromiseResult::Failed);
+        assert!(!contract.on_claim(auctioned_id(), bob()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], bob());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 1000);
+    }
+
+    fn ft_transfers(receipts: &near_sdk::serde_json::Value) -> Vec<(String, String)> {
+        receipts.as_array().unwrap().iter()
+            .filter(|receipt| receipt["actions"][0]["FunctionCall"]["method_name"] == "ft_transfer")
+            .map(|receipt| {
+                let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_str(receipt["actions"][0]["FunctionCall"]["args"].as_str().unwrap()).unwrap();
+                (args["receiver_id"].as_str().unwrap().to_string(), args["amount"].as_str().unwrap().to_string())
+            })
+            .collect()
     }
 
     #[test]
-    fn bid_with_commitment() {
+    fn reveal_bids_with_a_fungible_token() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
+        let token_id = "usdc.near".to_string();
+        let token_config = TokenConfig { reserve_price: U128(500), proceeds: TokenProceeds::Treasury { account_id: "treasury.near".to_string() } };
+        assert!(contract.set_token(token_id.clone(), Some(token_config.clone())));
+        assert_eq!(contract.get_token(token_id.clone()), Some(token_config));
 
+        // only the accepted tokens are bid in, the first bid sets the currency
         let context2 = get_context2(carol());
         testing_env!(context2);
         let commitment = "2s7YSBAHei";
-        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), true);
+        assert!(!contract.bid_with_token(auctioned_id(), commitment.as_bytes().to_vec(), Some("dai.near".to_string())));
+        assert!(contract.bid_with_token(auctioned_id(), commitment.as_bytes().to_vec(), Some(token_id.clone())));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(!contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+        assert!(contract.bid_with_token(auctioned_id(), commitment2.as_bytes().to_vec(), Some(token_id.clone())));
+
+        // the bids are only revealed in the currency of the auction
+        let msg = format!("{{\"account_id\":\"{}\",\"salt\":\"123\"}}", auctioned_id());
+        let context4 = get_context7("dai.near".to_string());
+        testing_env!(context4);
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), msg.clone()), U128(1000));
+
+        let context5 = get_context7(token_id.clone());
+        testing_env!(context5);
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), "{}".to_string()), U128(1000));
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), msg.clone()), U128(0));
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), msg.clone()), U128(1000));
+
+        let context6 = get_context6(bob());
+        testing_env!(context6);
+        assert!(!contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        let context7 = get_context7(token_id.clone());
+        testing_env!(context7);
+        assert_eq!(contract.ft_on_transfer(bob(), U128(1005), msg), U128(0));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.token_id, Some(token_id.clone()));
+        assert_eq!(auction.price, Some(U128(1000)));
+
+        // the refunds are transferred back in the token
+        let context8 = get_context7(bob());
+        testing_env!(context8);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert!(receipts.as_array().unwrap().iter().all(|receipt| receipt["actions"][0].get("Transfer").is_none()));
+        assert_eq!(ft_transfers(&receipts), vec![(bob(), "5".to_string()), (carol(), "1000".to_string())]);
+
+        // the proceeds go to the treasury of the token
+        let context9 = get_context7(alice());
+        set_promise_result(context9, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], token_id);
+        assert_eq!(ft_transfers(&receipts), vec![("treasury.near".to_string(), "1000".to_string())]);
     }
 
     #[test]
-    fn account_id_is_open_for_auction() {
-        let context = get_context(bob());
+    fn failed_token_refund_is_credited_to_the_token_balance() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let token_id = "usdc.near".to_string();
+
+        let context2 = get_context2(alice());
+        set_promise_result(context2, PromiseResult::Failed);
+        assert!(!contract.on_token_refund(token_id.clone(), bob(), U128(5)));
+        assert_eq!(contract.get_token_balance(token_id.clone(), bob()), U128(5));
+        assert_eq!(contract.get_balance(bob()), U128(0));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        assert!(contract.withdraw_token_balance(token_id.clone(), carol()));
+        assert!(!contract.withdraw_token_balance(token_id.clone(), carol()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], token_id);
+        assert_eq!(ft_transfers(&receipts), vec![(carol(), "5".to_string())]);
+    }
+
+    #[test]
+    fn claim_mints_the_token_of_the_name() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(contract.nft_token(auctioned_id()), None);
+
+        // the token is minted once the account is created
+        let context6 = get_context5(alice());
+        set_promise_result(context6, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+        let token = contract.nft_token(auctioned_id()).unwrap();
+        assert_eq!(token.owner_id, bob());
+        assert_eq!(token.metadata.title, Some(auctioned_id()));
+        let extra: near_sdk::serde_json::Value = near_sdk::serde_json::from_str(&token.metadata.extra.unwrap()).unwrap();
+        assert_eq!(extra["price"], "1000");
+        assert_eq!(extra["claim_block_height"], 1357);
+        assert_eq!(contract.nft_total_supply(), U128(1));
+        assert_eq!(contract.nft_tokens(None, None).len(), 1);
+        assert_eq!(contract.nft_tokens(Some(U128(1)), None).len(), 0);
+        assert_eq!(contract.nft_supply_for_owner(bob()), U128(1));
+        assert_eq!(contract.nft_metadata().spec, "nft-1.0.0");
+
+        // the owner transfers the token
+        let context7 = get_context5(bob());
+        testing_env!(VMContext { attached_deposit: 1, ..context7 });
+        contract.nft_transfer(carol(), auctioned_id(), None, None);
+        assert_eq!(contract.nft_token(auctioned_id()).unwrap().owner_id, carol());
+        assert_eq!(contract.nft_supply_for_owner(bob()), U128(0));
+        assert_eq!(contract.nft_tokens_for_owner(carol(), None, Some(10)).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sender doesn't own the token")]
+    fn only_the_owner_transfers_the_token() {
+        let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
+        assert!(contract.reserve_name(auctioned_id(), Some(bob())));
 
         let context2 = get_context2(bob());
         testing_env!(context2);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+
+        let context3 = get_context2(alice());
+        set_promise_result(context3, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+
+        let context4 = get_context2(carol());
+        testing_env!(VMContext { attached_deposit: 1, ..context4 });
+        contract.nft_transfer(carol(), auctioned_id(), None, None);
+    }
+
+    #[test]
+    fn auction_sub_accounts_of_the_suffix() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new_with_suffix(30, 35, AuctionKind::Sealed, U128(0), Some(alice()));
+        assert_eq!(contract.get_suffix(), Some(alice()));
+        assert!(contract.set_register_prices(vec![PriceTier { min_length: 3, price: U128(1000) }]));
+        let name = format!("premium.{}", alice());
+        let nested_name = format!("a.premium.{}", alice());
+        let other_name = "premium.bob.near".to_string();
+        assert!(!contract.reserve_name(other_name.clone(), None));
+
+        // the week of the name is scheduled on `label.suffix`
+        let block_index = 2 + auction_week(&name) * 30;
+        let context2 = get_context2(bob());
+        testing_env!(VMContext { block_index, ..context2 });
         let commitment = "2s7YSBAHei";
-        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), true);
+        assert!(!contract.bid(other_name, commitment.as_bytes().to_vec()));
+        assert!(contract.bid(name.clone(), commitment.as_bytes().to_vec()));
+
+        // the length of the label counts for the registration
+        let context3 = get_context2(bob());
+        testing_env!(VMContext { block_index: 2 + 52 * 30, ..context3 });
+        assert_eq!(contract.get_register_price(format!("abc.{}", alice())), Some(U128(1000)));
+        assert_eq!(contract.get_register_price(format!("ab.{}", alice())), None);
+        assert_eq!(contract.get_register_price(nested_name), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The suffix should be the account of the registrar")]
+    fn suffix_is_the_account_of_the_registrar() {
+        let context = get_context(carol());
+        testing_env!(context);
+        Registrar::new_with_suffix(30, 35, AuctionKind::Sealed, U128(0), Some(bob()));
+    }
+
+    #[test]
+    fn relayer_reveals_on_behalf_of_the_bidder() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()));
+
+        // the deposit of a wrong salt is refunded to the relayer
+        let context4 = get_context4(alice());
+        testing_env!(context4);
+        assert!(!contract.reveal_for(carol(), auctioned_id(), 1000, "124".to_string()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], alice());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 1000);
+
+        let context5 = get_context4(alice());
+        testing_env!(context5);
+        assert!(contract.reveal_for(carol(), auctioned_id(), 1000, "123".to_string()));
+
+        let context6 = get_context6(bob());
+        testing_env!(context6);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        // carol gets the refund of the bid the relayer revealed
+        let context7 = get_context7(carol());
+        testing_env!(context7);
+        assert!(contract.withdraw(auctioned_id()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], carol());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 1000);
+    }
+
+    fn sign(seed: &[u8], message: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let secret = ed25519_dalek::SecretKey::from_bytes(seed).unwrap();
+        let public_key = ed25519_dalek::PublicKey::from(&secret);
+        let signature = ed25519_dalek::ExpandedSecretKey::from(&secret).sign(message, &public_key);
+        (public_key.to_bytes().to_vec(), signature.to_bytes().to_vec())
+    }
+
+    fn signed_bid(seed: &[u8; 32], payload: &BidPayload) -> Base64VecU8 {
+        let (_, signature) = sign(seed, &payload.try_to_vec().unwrap());
+        Base64VecU8(signature)
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn bid_with_a_signed_payload() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+
+        // bob registers the key it signs its bids with
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        let (public_key, _) = sign(&[7; 32], &[]);
+        let mut key = vec![0];
+        key.extend_from_slice(&public_key);
+        assert!(!contract.register_bid_key(Base58PublicKey(vec![1; 65])));
+        assert!(contract.register_bid_key(Base58PublicKey(key.clone())));
+        assert_eq!(contract.get_bid_key(bob()), Some(Base58PublicKey(key)));
+
+        // carol relays the bid of bob
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        let payload = BidPayload {
+            registrar_id: alice(),
+            bidder_account_id: bob(),
+            account_id: auctioned_id(),
+            commitment: "2s7YSBAHei".as_bytes().to_vec(),
+            nonce: 1,
+            expiry: 1300,
+        };
+        assert!(!contract.bid_signed(payload.clone(), signed_bid(&[8; 32], &payload)));
+        assert!(!contract.bid_signed(BidPayload { registrar_id: carol(), ..payload.clone() }, signed_bid(&[7; 32], &payload)));
+        assert!(!contract.bid_signed(BidPayload { expiry: 1291, ..payload.clone() }, signed_bid(&[7; 32], &BidPayload { expiry: 1291, ..payload.clone() })));
+        assert!(contract.bid_signed(payload.clone(), signed_bid(&[7; 32], &payload)));
+        assert_eq!(contract.get_bid_nonce(bob()), 1);
+        let bids = contract.list_bids(auctioned_id(), 0, 10);
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].bidder_account_id, bob());
+
+        // the payload can't be replayed, even on another name
+        let payload2 = BidPayload { account_id: auctioned_id2(), ..payload.clone() };
+        assert!(!contract.bid_signed(payload.clone(), signed_bid(&[7; 32], &payload)));
+        assert!(!contract.bid_signed(payload2.clone(), signed_bid(&[7; 32], &payload2)));
+
+        // bob reveals its bid as usual
+        let context4 = get_context4(bob());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+
+        // signed bids are no longer accepted once the key is removed
+        let context5 = get_context2(bob());
+        testing_env!(context5);
+        assert!(contract.remove_bid_key());
+        let payload3 = BidPayload { nonce: 2, ..payload2 };
+        assert!(!contract.bid_signed(payload3.clone(), signed_bid(&[7; 32], &payload3)));
+    }
+
+    const BLIND_BID_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+    // the receipts are checked through their debug output, the blind bid deposit doesn't fit in a json number
+    fn has_transfer(receiver_id: &str, deposit: Balance) -> bool {
+        let transfer = format!("receiver_id: {:?}, actions: [Transfer(TransferAction {{ deposit: {} }})]", receiver_id, deposit);
+        format!("{:?}", env::created_receipts()).contains(&transfer)
+    }
+
+    fn blind_commitment(account_id: &AccountId, masked_amount: Balance, salt: &str) -> Vec<u8> {
+        let hash = env::sha256(format!("{}:{}:{}", account_id, masked_amount, salt).as_bytes());
+        bs58::encode(hash).into_string().into_bytes()
     }
 
     #[test]
-    fn is_not_open_for_auction_min() {
-        let context = get_context(alice());
+    fn blind_bid_is_matched_to_its_auction_at_reveal() {
+        let unlisted_id = || "unlisted.near".to_string();
+        let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
-        let context2 = get_context3(alice());
+        // bob doesn't name the accounts it bids on, the deposit pays for the storage of the blind bids
+        let context2 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context2(bob()) };
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), false);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1005, "123")));
+        assert!(!contract.bid_blind(blind_commitment(&auctioned_id(), 1005, "123")));
+        assert!(contract.bid_blind(blind_commitment(&unlisted_id(), 1000, "123")));
+        assert!(contract.list_auctions(0, 10, None).is_empty());
+
+        let context3 = get_context2(carol());
+        testing_env!(context3);
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+
+        // the blind bid can't be revealed while bidding
+        let context4 = VMContext { attached_deposit: 1005, ..get_context2(bob()) };
+        testing_env!(context4);
+        assert!(!contract.reveal_blind(auctioned_id(), 1005, "123".to_string()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], bob());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 1005);
+
+        // the blind bid joins the auction of the disclosed name
+        let context5 = VMContext { account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context6(bob()) };
+        testing_env!(context5);
+        assert!(!contract.reveal_blind(auctioned_id(), 1005, "124".to_string()));
+        assert!(contract.reveal_blind(auctioned_id(), 1005, "123".to_string()));
+        assert!(!contract.reveal_blind(auctioned_id(), 1005, "123".to_string()));
+        let context6 = get_context4(carol());
+        testing_env!(context6);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        assert_eq!(contract.get_account_activity(bob()).len(), 1);
+
+        // the other blind bid could still join the auction, it isn't settled before the end of the reveal period
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, None);
+        assert!(!contract.withdraw(auctioned_id()));
+
+        // unlisted.near wasn't on the market when bob bid on it, the deposit is refunded and the bid dropped
+        let context7 = VMContext { account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context4(bob()) };
+        testing_env!(context7);
+        assert!(!contract.reveal_blind(unlisted_id(), 1000, "123".to_string()));
+        assert!(has_transfer(&bob(), BLIND_BID_DEPOSIT));
+        assert!(has_transfer(&bob(), 1000));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, Some(bob()));
+        assert_eq!(auction.price, Some(U128(1000)));
+        let context8 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context4(bob()) };
+        testing_env!(context8);
+        assert!(contract.bid_blind(blind_commitment(&unlisted_id(), 1000, "123")));
+        assert_eq!(contract.list_auctions(0, 10, None).len(), 1);
     }
 
     #[test]
-    fn is_not_open_for_auction_max() {
-        let context = get_context(alice());
+    fn blind_bids_hold_the_settlement_until_they_expire() {
+        let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
-        let context2 = get_context4(alice());
+        let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-        assert_eq!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()), false);
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+        let context3 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context2(bob()) };
+        testing_env!(context3);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1005, "123")));
+
+        // carol revealed, but bob's blind bid could still be disclosed
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(!contract.claim(auctioned_id(), public_key.clone()));
+
+        // the claim at the end of the reveal period removes the expired blind bid and refunds its deposit
+        let context5 = VMContext { block_index: 1357, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context7(carol()) };
+        testing_env!(context5);
+        assert!(contract.claim(auctioned_id(), public_key));
+        assert!(has_transfer(&bob(), BLIND_BID_DEPOSIT));
+        let context6 = VMContext { block_index: 1357, ..get_context6(bob()) };
+        testing_env!(context6);
+        assert!(!contract.reveal_blind(auctioned_id(), 1005, "123".to_string()));
+        let context7 = VMContext { block_index: 1357, attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context6(bob()) };
+        testing_env!(context7);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1005, "123")));
     }
 
     #[test]
-    fn bidder_already_bid() {
+    fn blind_bid_starts_the_auction_at_its_block() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
-        let context2 = get_context2(carol());
+        let context2 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context2(bob()) };
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1000, "123")));
 
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+        // nobody else bid, the auction is started at the block of the blind bid
+        let context3 = VMContext { account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context4(bob()) };
+        testing_env!(context3);
+        assert!(contract.reveal_blind(auctioned_id(), 1000, "123".to_string()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.start_block_height, 1292);
+        assert_eq!(auction.winning_account_id, Some(bob()));
 
-        let commitment2 = "2s7YSBAHei2";
-        assert_eq!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()), false);
+        // a blind bid disclosed after the reveal period is dropped
+        let context4 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context2(carol()) };
+        testing_env!(context4);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1000, "123")));
+        let context5 = VMContext { account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context5(carol()) };
+        testing_env!(context5);
+        assert!(!contract.reveal_blind(auctioned_id(), 1000, "123".to_string()));
+        let context6 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context5(carol()) };
+        testing_env!(context6);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id(), 1000, "123")));
     }
 
     #[test]
-    fn auction_is_expired() {
+    fn reveal_bids_from_the_escrow_balance() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
-        let context2 = get_context2(carol());
+        // bob keeps an escrow balance instead of attaching its bids
+        let context2 = VMContext { attached_deposit: 3000, ..get_context2(bob()) };
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
-
-        let context3 = get_context4(bob());
+        assert!(contract.deposit_escrow());
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+        let context3 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context2(bob()) };
         testing_env!(context3);
-        let commitment2 = "2s7YSBAHei2";
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id2(), 1000, "123")));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(3000));
 
-        assert_eq!(contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()), false);
+        let context4 = get_context2(carol());
+        testing_env!(context4);
+        assert!(!contract.deposit_escrow());
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+
+        // the reveals draw from the escrow, a failed reveal leaves it untouched
+        let context5 = VMContext { account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context7(bob()) };
+        testing_env!(context5);
+        assert!(!contract.reveal_from_escrow(auctioned_id(), 1005, "124".to_string()));
+        assert!(!contract.reveal_from_escrow(auctioned_id(), 5000, "123".to_string()));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(3000));
+        assert!(contract.reveal_from_escrow(auctioned_id(), 1005, "123".to_string()));
+        assert!(contract.reveal_blind_from_escrow(auctioned_id2(), 1000, "123".to_string()));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(995));
+
+        // revealing again draws nothing
+        assert!(contract.reveal_from_escrow(auctioned_id(), 1005, "123".to_string()));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(995));
+        assert_eq!(env::created_receipts().len(), 2);
+        assert!(has_transfer(&bob(), BLIND_BID_DEPOSIT));
+
+        let context6 = get_context4(carol());
+        testing_env!(context6);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        let auctions = contract.list_auctions(0, 10, None);
+        assert!(auctions.iter().all(|auction| auction.winning_account_id == Some(bob())));
+
+        // the rest of the escrow is withdrawn
+        let context7 = get_context7(bob());
+        testing_env!(context7);
+        assert!(!contract.withdraw_escrow(U128(996)));
+        assert!(contract.withdraw_escrow(U128(995)));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(0));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], bob());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 995);
     }
 
     #[test]
-    fn reveal_the_amount() {
+    fn update_and_cancel_bids_while_bidding() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
+        assert!(contract.set_bid_cancel_fee(U128(10)));
 
-        let context2 = get_context2(carol());
+        let context2 = get_context2(bob());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+        assert!(!contract.set_bid_cancel_fee(U128(0)));
+        assert!(!contract.update_bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4T".as_bytes().to_vec()));
+        assert!(!contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
 
-        let context3 = get_context4(carol());
+        // bob fixes the typo in its commitment
+        let context3 = VMContext { block_index: 1293, ..get_context2(bob()) };
         testing_env!(context3);
+        assert!(contract.update_bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
 
-        let masked_amount: Balance = 1000;
-        let salt: String = "123".to_string();
-
-        assert_eq!(contract.reveal(auctioned_id(), masked_amount, salt), true);
+        // carol cancels her bid, the fee is kept and the rest of the deposit refunded
+        let context4 = get_context2(carol());
+        testing_env!(context4);
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id2(), "2s7YSBAHei".as_bytes().to_vec()));
+        let context5 = VMContext { attached_deposit: 9, ..get_context2(carol()) };
+        testing_env!(context5);
+        assert!(!contract.cancel_bid(auctioned_id()));
+        let context6 = VMContext { attached_deposit: 25, ..get_context2(carol()) };
+        testing_env!(context6);
+        assert!(contract.cancel_bid(auctioned_id()));
+        assert!(!contract.cancel_bid(auctioned_id()));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        assert_eq!(receipts[0]["receiver_id"], carol());
+        assert_eq!(receipts[0]["actions"][0]["Transfer"]["deposit"], 15);
+        assert_eq!(contract.get_account_activity(carol()), vec![ParticipationView { account_id: auctioned_id2(), status: ParticipationStatus::Bidding }]);
+
+        // the last bid drops the auction
+        let context7 = VMContext { attached_deposit: 10, ..get_context2(carol()) };
+        testing_env!(context7);
+        assert!(contract.cancel_bid(auctioned_id2()));
+        assert_eq!(contract.list_auctions(0, 10, None).len(), 1);
+        assert!(contract.get_account_activity(carol()).is_empty());
+
+        // the bids can't change once the bidding period is over
+        let context8 = get_context6(bob());
+        testing_env!(context8);
+        assert!(!contract.update_bid(auctioned_id(), "2s7YSJaE4T".as_bytes().to_vec()));
+        assert!(!contract.cancel_bid(auctioned_id()));
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].winning_account_id, Some(bob()));
     }
 
     #[test]
-    fn dont_reveal_if_auction_in_progress() {
+    fn winner_cant_withdraw_before_claiming() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
 
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+        let context3 = get_context2(bob());
+        testing_env!(context3);
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
 
-        let masked_amount: Balance = 1000;
-        let salt: String = "123".to_string();
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
 
-        assert_eq!(contract.reveal(auctioned_id(), masked_amount, salt), false);
+        let context5 = get_context6(bob());
+        testing_env!(context5);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+
+        // the auction is settled and unclaimed, bob can neither withdraw nor withdraw all
+        let context6 = get_context7(bob());
+        testing_env!(context6);
+        assert!(!contract.withdraw(auctioned_id()));
+        assert!(!contract.withdraw_all(10));
+        assert_eq!(env::created_receipts().len(), 0);
+
+        // bob claims and is refunded once, 5 above the price
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(contract.claim(auctioned_id(), public_key));
+        let receipts = near_sdk::serde_json::to_value(env::created_receipts()).unwrap();
+        let refunds: Vec<(String, u64)> = receipts
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|receipt| {
+                let deposit = receipt["actions"][0]["Transfer"]["deposit"].as_u64()?;
+                Some((receipt["receiver_id"].as_str()?.to_string(), deposit))
+            })
+            .collect();
+        assert!(refunds.contains(&(bob(), 5)));
+        assert!(refunds.contains(&(carol(), 1000)));
+        assert!(!refunds.contains(&(bob(), 1005)));
     }
 
     #[test]
-    fn withdraw_after_all_revealed() {
+    fn claim_refunds_the_surplus_of_the_winning_bid() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
 
         let context3 = get_context2(bob());
         testing_env!(context3);
-        let commitment2 = "2s7YSJaE4S";
-
-        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
 
         let context4 = get_context4(carol());
         testing_env!(context4);
-        let masked_amount: Balance = 1000;
-        let salt: String = "123".to_string();
-        contract.reveal(auctioned_id(), masked_amount, salt);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
 
         let context5 = get_context6(bob());
         testing_env!(context5);
-        let masked_amount2: Balance = 1005;
-        let salt2: String = "123".to_string();
-        contract.reveal(auctioned_id(), masked_amount2, salt2);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
 
-        assert_eq!(contract.withdraw(auctioned_id()), true);
+        // bob bid 1005 and pays the second price of 1000, the surplus of 5 is refunded with carol's bid
+        let balance = env::account_balance();
+        let public_key = Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec());
+        assert!(contract.claim(auctioned_id(), public_key.clone()));
+        assert_eq!(env::account_balance(), balance - 5 - 1000);
+
+        // the surplus isn't paid again
+        assert!(!contract.claim(auctioned_id(), public_key));
+        assert_eq!(env::account_balance(), balance - 5 - 1000);
     }
 
     #[test]
-    fn withdraw_after_reveal_period_expired() {
+    fn list_auctions_paginates_over_the_auctions_in_a_phase() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
@@ -745,23 +4911,37 @@ mod tests {
         let context2 = get_context2(carol());
         testing_env!(context2);
         let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
 
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
-
-        let context3 = get_context2(bob());
+        let context3 = get_context9(bob());
         testing_env!(context3);
         let commitment2 = "2s7YSJaE4S";
+        assert!(contract.bid(auctioned_id2(), commitment2.as_bytes().to_vec()));
 
-        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
-
-        let context4 = get_context5(carol());
+        let context4 = get_context7(carol());
         testing_env!(context4);
-        
-        assert_eq!(contract.withdraw(auctioned_id()), true);
+        let auctions = contract.list_auctions(0, 10, Some(AuctionPhase::Reveal));
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].account_id, auctioned_id());
+        let auctions = contract.list_auctions(0, 10, Some(AuctionPhase::Bidding));
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].account_id, auctioned_id2());
+        assert_eq!(contract.list_auctions(1, 10, Some(AuctionPhase::Bidding)).len(), 0);
+        assert_eq!(contract.list_auctions(0, 10, Some(AuctionPhase::Closed)).len(), 0);
+
+        let context5 = get_context5(carol());
+        testing_env!(context5);
+        let auctions = contract.list_auctions(0, 10, Some(AuctionPhase::Closed));
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].account_id, auctioned_id());
+        let auctions = contract.list_auctions(0, 1, Some(AuctionPhase::Reveal));
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].account_id, auctioned_id2());
+        assert_eq!(contract.list_auctions(0, 0, Some(AuctionPhase::Reveal)).len(), 0);
     }
 
     #[test]
-    fn withdraw_when_reveal_in_progress() {
+    fn cancelled_auction_is_no_longer_listed() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
@@ -769,199 +4949,243 @@ mod tests {
         let context2 = get_context2(carol());
         testing_env!(context2);
         let commitment = "2s7YSBAHei";
+        assert!(contract.bid(auctioned_id(), commitment.as_bytes().to_vec()));
+        assert_eq!(contract.list_auctions(0, 10, Some(AuctionPhase::Bidding)).len(), 1);
 
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
-
-        let context3 = get_context4(carol());
-        testing_env!(context3);
-
-        assert_eq!(contract.withdraw(auctioned_id()), false);
+        assert!(contract.cancel_bid(auctioned_id()));
+        assert_eq!(contract.list_auctions(0, 10, Some(AuctionPhase::Bidding)).len(), 0);
+        assert_eq!(contract.list_auctions(0, 10, None).len(), 0);
     }
 
     #[test]
-    fn withdraw_but_reveal_in_progress_and_not_all_bidders_revealed() {
+    fn blind_bids_only_hold_the_auctions_of_their_week() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-
-        contract.bid(auctioned_id(), commitment.as_bytes().to_vec());
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
 
-        let context3 = get_context2(bob());
+        // the deposit has to cover the storage of the blind bid
+        let context3 = get_context7(bob());
         testing_env!(context3);
-        let commitment2 = "2s7YSJaE4S";
-
-        contract.bid(auctioned_id(), commitment2.as_bytes().to_vec());
+        assert!(!contract.bid_blind(blind_commitment(&auctioned_id2(), 1000, "123")));
+        let context4 = VMContext { attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context7(bob()) };
+        testing_env!(context4);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id2(), 1000, "123")));
 
-        let context5 = get_context6(bob());
+        // bob's blind bid was placed after the week of auctioned_id1.near, it can't hold its settlement
+        let context5 = get_context4(carol());
         testing_env!(context5);
-        let masked_amount2: Balance = 1005;
-        let salt2: String = "123".to_string();
-        contract.reveal(auctioned_id(), masked_amount2, salt2);
-
-        assert_eq!(contract.withdraw(auctioned_id()), false);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, Some(carol()));
+
+        // the next blind bid removes the expired one and refunds its deposit
+        let context6 = VMContext { block_index: 1387, attached_deposit: BLIND_BID_DEPOSIT, account_balance: 2 * BLIND_BID_DEPOSIT, ..get_context7(carol()) };
+        testing_env!(context6);
+        assert!(contract.bid_blind(blind_commitment(&auctioned_id2(), 1000, "123")));
+        assert!(has_transfer(&bob(), BLIND_BID_DEPOSIT));
     }
 
     #[test]
-    fn check_contract_balance_after_multiple_withdraws() {
+    fn repeated_reveal_refunds_the_deposit() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-
-        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
 
-        let context3 = get_context4(carol());
+        // a relayer reveals carol's bid, then carol reveals it too
+        let context3 = get_context4(alice());
         testing_env!(context3);
+        assert!(contract.reveal_for(carol(), auctioned_id(), 1000, "123".to_string()));
+        let context4 = get_context4(carol());
+        testing_env!(context4);
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        assert!(has_transfer(&carol(), 1000));
+        let bids = contract.list_bids(auctioned_id(), 0, 10);
+        assert_eq!(bids[0].amount, Some(U128(1000)));
 
-        let masked_amount: Balance = 1000;
-        let salt: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount, salt) {
-            assert!(false);
-        }
+        // the relayer's second reveal is refunded as well
+        let context5 = get_context4(alice());
+        testing_env!(context5);
+        assert!(contract.reveal_for(carol(), auctioned_id(), 1000, "123".to_string()));
+        assert!(has_transfer(&alice(), 1000));
+    }
 
-        if !contract.reveal(auctioned_id(), masked_amount, "123".to_string()) {
-            assert!(false);
-        }
-        
-        if !contract.withdraw(auctioned_id()) {
-            assert!(false);
-        }
+    #[test]
+    fn relayer_reveals_from_the_escrow_of_the_bidder() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
 
-        assert_eq!( env::account_balance() == 2123, true);
+        // bob locks its funds ahead of the reveal
+        let context2 = VMContext { attached_deposit: 1005, ..get_context2(bob()) };
+        testing_env!(context2);
+        assert!(contract.deposit_escrow());
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+
+        // a relayer holding the salt reveals without attaching anything
+        let context3 = get_context7(alice());
+        testing_env!(context3);
+        assert!(!contract.reveal_for_from_escrow(bob(), auctioned_id(), 1005, "124".to_string()));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(1005));
+        assert!(contract.reveal_for_from_escrow(bob(), auctioned_id(), 1005, "123".to_string()));
+        assert!(contract.reveal_for_from_escrow(bob(), auctioned_id(), 1005, "123".to_string()));
+        assert_eq!(contract.get_escrow_balance(bob()), U128(0));
+        assert!(env::created_receipts().is_empty());
+        let bids = contract.list_bids(auctioned_id(), 0, 10);
+        assert_eq!(bids[0].amount, Some(U128(1005)));
     }
 
     #[test]
-    fn claim_the_account() {
+    fn delisted_token_still_reveals_its_auctions() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
+        let token_id = "usdc.near".to_string();
+        let token_config = TokenConfig { reserve_price: U128(500), proceeds: TokenProceeds::Burn };
+        assert!(contract.set_token(token_id.clone(), Some(token_config)));
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2s7YSBAHei";
-        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        assert!(contract.bid_with_token(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec(), Some(token_id.clone())));
+        assert!(contract.set_token(token_id.clone(), None));
+        assert!(!contract.bid_with_token(auctioned_id2(), "2s7YSBAHei".as_bytes().to_vec(), Some(token_id.clone())));
 
-        let context3 = get_context2(bob());
+        // the auction is revealed in the token it was bid in
+        let msg = format!("{{\"account_id\":\"{}\",\"salt\":\"123\"}}", auctioned_id());
+        let context3 = get_context7(token_id.clone());
         testing_env!(context3);
-        let commitment2 = "2s7YSJaE4S";
-        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
-            assert!(false);
-        }
-
-        let context4 = get_context4(carol());
-        testing_env!(context4);
-        let masked_amount: Balance = 1000;
-        let salt: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount, salt) {
-            assert!(false);
-        }
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), msg), U128(0));
+        let msg2 = format!("{{\"account_id\":\"{}\",\"salt\":\"123\"}}", auctioned_id2());
+        assert_eq!(contract.ft_on_transfer(carol(), U128(1000), msg2), U128(1000));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.winning_account_id, Some(carol()));
+    }
 
-        let context5 = get_context6(bob());
-        testing_env!(context5);
-        let masked_amount2: Balance = 1005;
-        let salt2: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
-            assert!(false);
-        }
+    #[test]
+    fn failed_reveal_refunds_the_deposit() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
 
-        if !contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())) {
-            assert!(false);
-        }
+        let context2 = get_context2(carol());
+        testing_env!(context2);
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
 
-        assert_eq!( env::account_balance() == 1239, true);
+        let context3 = get_context4(carol());
+        testing_env!(context3);
+        assert!(!contract.reveal(auctioned_id(), 1000, "124".to_string()));
+        assert!(has_transfer(&carol(), 1000));
+        assert!(!contract.reveal(auctioned_id(), 999, "123".to_string()));
+        assert_eq!(env::created_receipts().len(), 4);
     }
 
     #[test]
-    fn claim_fails_if_the_highest_bid_is_0() {
+    fn withdraw_all_counts_every_auction_against_the_limit() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
 
         let context2 = get_context2(carol());
         testing_env!(context2);
-        let commitment = "2ESvwk";
-        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id2(), "2s7YSBAHei".as_bytes().to_vec()));
 
         let context3 = get_context2(bob());
         testing_env!(context3);
-        let commitment2 = "2ESvwk";
-        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+        assert!(contract.bid(auctioned_id2(), "2s7YSJaE4S".as_bytes().to_vec()));
 
-        let context4 = get_context7(carol());
+        let context4 = get_context4(carol());
         testing_env!(context4);
-        let masked_amount: Balance = 0;
-        let salt: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount, salt) {
-            assert!(false);
-        }
-
-        let context5 = get_context7(bob());
+        assert!(contract.reveal(auctioned_id(), 1000, "123".to_string()));
+        assert!(contract.reveal(auctioned_id2(), 1000, "123".to_string()));
+        let context5 = get_context6(bob());
         testing_env!(context5);
-        let masked_amount2: Balance = 0;
-        let salt2: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
-            assert!(false);
-        }
-
-        assert_eq!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())), false);
+        assert!(contract.reveal(auctioned_id(), 1005, "123".to_string()));
+        assert!(contract.reveal(auctioned_id2(), 1005, "123".to_string()));
+
+        // bob's claim refunds carol's bid on auctioned_id1.near
+        let context6 = get_context7(bob());
+        testing_env!(context6);
+        assert!(contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        let context7 = get_context7(alice());
+        set_promise_result(context7, PromiseResult::Successful(vec![]));
+        assert!(contract.on_claim(auctioned_id(), bob()));
+
+        // the first call only looks at the claimed auction, nothing is left to withdraw and it is forgotten
+        let context8 = get_context7(carol());
+        testing_env!(context8);
+        assert!(!contract.withdraw_all(1));
+        let activity = contract.get_account_activity(carol());
+        assert_eq!(activity, vec![ParticipationView { account_id: auctioned_id2(), status: ParticipationStatus::Refundable }]);
+        assert!(contract.withdraw_all(1));
+        let activity = contract.get_account_activity(carol());
+        assert_eq!(activity, vec![ParticipationView { account_id: auctioned_id2(), status: ParticipationStatus::Withdrawn }]);
     }
 
     #[test]
-    fn winner_pays_highest_bid_if_second_highest_bid_is_0() {
+    fn sealed_bid_restarts_a_failed_open_auction_as_sealed() {
         let context = get_context(carol());
         testing_env!(context);
         let mut contract = Registrar::new(30, 35);
+        assert!(contract.set_auction_kind(auctioned_id(), Some(AuctionKind::Open)));
 
-        let context2 = get_context2(carol());
+        let context2 = get_context8(bob());
         testing_env!(context2);
-        let commitment = "2ESvwk";
-        if !contract.bid(auctioned_id(), commitment.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        assert!(contract.bid_open(auctioned_id()));
 
-        let context3 = get_context2(bob());
-        testing_env!(context3);
-        let commitment2 = "2s7YSJaE4S";
-        if !contract.bid(auctioned_id(), commitment2.as_bytes().to_vec()) {
-            assert!(false);
-        }
+        // the claim of bob failed and the name is auctioned in the sealed format again
+        let mut auction = contract.auctions.get(&auctioned_id()).unwrap();
+        auction.claim_status = ClaimStatus::Failed;
+        contract.auctions.insert(&auctioned_id(), &auction);
 
-        let context4 = get_context7(carol());
+        let context3 = get_context9(carol());
+        testing_env!(context3);
+        assert!(contract.set_auction_kind(auctioned_id(), None));
+        assert!(contract.bid(auctioned_id(), "2s7YSBAHei".as_bytes().to_vec()));
+        let context4 = get_context9(bob());
         testing_env!(context4);
-        let masked_amount: Balance = 0;
-        let salt: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount, salt) {
-            assert!(false);
-        }
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+        let auction = &contract.list_auctions(0, 10, None)[0];
+        assert_eq!(auction.kind, AuctionKind::Sealed);
+        assert_eq!(auction.start_block_height, 1300);
+        assert_eq!(auction.num_bids, 2);
+    }
 
-        let context5 = get_context6(bob());
-        testing_env!(context5);
-        let masked_amount2: Balance = 1005;
-        let salt2: String = "123".to_string();
-        if !contract.reveal(auctioned_id(), masked_amount2, salt2){
-            assert!(false);
-        }
+    #[test]
+    #[should_panic(expected = "Commitment should not be empty")]
+    fn bid_cant_be_updated_to_an_empty_commitment() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
 
-        if !contract.claim(auctioned_id(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())) {
-            assert!(false);
-        }
+        let context2 = get_context2(bob());
+        testing_env!(context2);
+        assert!(contract.bid(auctioned_id(), "2s7YSJaE4S".as_bytes().to_vec()));
+        contract.update_bid(auctioned_id(), Vec::new());
+    }
 
-        assert_eq!( env::account_balance() == 2239, true);
+    #[test]
+    fn long_name_is_registered_during_its_week() {
+        let context = get_context(carol());
+        testing_env!(context);
+        let mut contract = Registrar::new(30, 35);
+        let long_id = "a_long_name_that_is_never_auctioned.near".to_string();
+        assert!(contract.set_register_prices(vec![PriceTier { min_length: 32, price: U128(100) }]));
+
+        // the name isn't auctioned even in the week of its hash
+        let block_index = 2 + auction_week(&long_id) * 30;
+        let context2 = get_context8(bob());
+        testing_env!(VMContext { block_index, ..context2 });
+        assert!(!contract.bid(long_id.clone(), "2s7YSJaE4S".as_bytes().to_vec()));
+        assert!(contract.register(long_id.clone(), Base58PublicKey("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".as_bytes().to_vec())));
+        assert_eq!(contract.list_auctions(0, 10, None)[0].kind, AuctionKind::Fixed);
     }
 }
-